@@ -1,32 +1,45 @@
+use num::bigint::BigUint;
+use num::{CheckedAdd, CheckedSub, One, Zero};
 use std::collections::HashMap;
-use std::io::BufRead;
-use std::io::{stdin, BufReader, Read};
-use thiserror::Error;
+
+#[cfg(test)]
+#[path = "../xorshift.rs"]
+mod xorshift;
 
 fn main() {
-    /*
-    let mut r = BufReader::new(stdin());
-    let mut data = String::new();
-    r.read_to_string(&mut data).unwrap();
-    */
-    let data = include_str!("../inputs/day10.txt");
-    let data: Vec<_> = data.lines().filter_map(|s| s.parse::<u8>().ok()).collect();
-    let dist = get_distribution(&data);
+    // day10 [--max-step N]
+    let args: Vec<String> = std::env::args().collect();
+    let max_step = match args.iter().position(|a| a == "--max-step") {
+        Some(i) => match args.get(i + 1).and_then(|n| n.parse().ok()) {
+            Some(n) if n > 0 => n,
+            _ => {
+                eprintln!("--max-step needs a number bigger than 0");
+                std::process::exit(1);
+            }
+        },
+        None => 3,
+    };
+    let data = include_str!("../../inputs/day10.txt");
+    let data: Vec<u32> = data.lines().filter_map(|s| s.parse().ok()).collect();
+    let dist = get_distribution(&data, max_step);
 
     for (k, v) in dist.iter() {
         println!("{}: {}", k, v);
     }
-    let out = dist.get(&1u8).unwrap() * dist.get(&3u8).unwrap();
-    println!("result {}", out);
-    let out = total_permutations(&data);
+    let ones = dist.get(&1).copied().unwrap_or(0);
+    let threes = dist.get(&3).copied().unwrap_or(0);
+    println!("result {}", ones * threes);
+    let out: BigUint = arrangements(&data, max_step).expect("a BigUint doesn't overflow");
     println!("total perms: {}", out);
 }
 
-fn get_distribution(jolts: &[u8]) -> HashMap<u8, i32> {
-    let mut data = vec![0u8];
+/// How many times each gap comes up going from the outlet to the device, which
+/// takes `max_step` more than the biggest adapter, same as in `arrangements`
+fn get_distribution(jolts: &[u32], max_step: u32) -> HashMap<u32, i32> {
+    let mut data = vec![0u32];
     data.extend_from_slice(jolts);
     data.sort_unstable();
-    data.push(data.last().unwrap() + 3);
+    data.push(data.last().unwrap() + max_step);
     data.windows(2).fold(HashMap::new(), |mut e, r| {
         let diff = r[1] - r[0];
         e.entry(diff).and_modify(|x| *x += 1).or_insert(1);
@@ -34,38 +47,63 @@ fn get_distribution(jolts: &[u8]) -> HashMap<u8, i32> {
     })
 }
 
-fn total_permutations(data: &[u8]) -> u64 {
-    let mut data = data.to_vec();
-    data.push(0);
+/// How many ways there are to chain adapters from the outlet (0 jolts) to the device,
+/// where each one takes anything from 1 to `max_step` jolts lower than itself and the
+/// device takes `max_step` more than the biggest adapter (so 3, like the puzzle says,
+/// when that's the step). Adapters with the same rating count as different adapters.
+///
+/// Going up in rating, the ways to reach an adapter are the ways to reach everything
+/// it can plug into, which is a window of the adapters below it that only moves up,
+/// so its total gets kept as things come into and drop out of it.
+/// `None` if the count doesn't fit in a `C`. Counts on the way there can be too
+/// big without that mattering, if a gap wider than `max_step` comes after them.
+fn arrangements<C>(jolts: &[u32], max_step: u32) -> Option<C>
+where
+    C: Clone + Zero + One + CheckedAdd + CheckedSub,
+{
+    let mut data = vec![0u64];
+    data.extend(jolts.iter().map(|j| *j as u64));
     data.sort_unstable();
-    data.push(data.last().unwrap() + 3);
-
-    let trib = [1,1,2,4,7];
-
-    let diffs = data.iter().zip(&data[1..])
-        .map(|(prev, current)| {
-            current - prev
-        })
-        .collect::<Vec<u8>>();
-
-    diffs
-        .split(|diff| *diff == 3)
-        .filter(|diffs| !diffs.is_empty())
-        .map(|ones| ones.len())
-        .map(|i| trib[i])
-        .product()
+    data.push(data[data.len() - 1] + max_step as u64);
+
+    // None for anything too big to count
+    let mut ways: Vec<Option<C>> = vec![None; data.len()];
+    ways[0] = Some(C::one());
+    // ways to get to everything in data[lo..hi]
+    let mut window = Some(C::zero());
+    let (mut lo, mut hi) = (0, 0);
+    for i in 1..data.len() {
+        // everything lower than this one can be plugged into, but not its equals
+        while data[hi] < data[i] {
+            window = window.and_then(|w| w.checked_add(ways[hi].as_ref()?));
+            hi += 1;
+        }
+        while data[i] - data[lo] > max_step as u64 {
+            window = window.and_then(|w| w.checked_sub(ways[lo].as_ref()?));
+            lo += 1;
+        }
+        if window.is_none() {
+            // whatever was too big might have dropped out, so add it up again
+            window = ways[lo..hi]
+                .iter()
+                .try_fold(C::zero(), |sum, w| sum.checked_add(w.as_ref()?));
+        }
+        ways[i] = window.clone();
+    }
+    ways.pop().flatten()
 }
 
 #[cfg(test)]
 mod tests {
-    const DATA: [u8; 11] = [16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4];
+    const DATA: [u32; 11] = [16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4];
+    use super::xorshift::XorShift;
     use super::*;
 
     #[test]
     fn test_joltage_dist() {
-        let dist = get_distribution(&DATA);
-        assert_eq!(dist.get(&1u8).cloned(), Some(7));
-        assert_eq!(dist.get(&3u8).cloned(), Some(5));
+        let dist = get_distribution(&DATA, 3);
+        assert_eq!(dist.get(&1u32).cloned(), Some(7));
+        assert_eq!(dist.get(&3u32).cloned(), Some(5));
     }
 
     #[test]
@@ -75,9 +113,9 @@ mod tests {
             8, 17, 7, 9, 4, 2, 34, 10, 3,
         ];
         
-        let dist = get_distribution(&data);
-        assert_eq!(dist.get(&1u8).cloned(), Some(22));
-        assert_eq!(dist.get(&3u8).cloned(), Some(10));
+        let dist = get_distribution(&data, 3);
+        assert_eq!(dist.get(&1u32).cloned(), Some(22));
+        assert_eq!(dist.get(&3u32).cloned(), Some(10));
     }
 
     #[test]
@@ -86,8 +124,105 @@ mod tests {
             28, 33, 18, 42, 31, 14, 46, 20, 48, 47, 24, 23, 49, 45, 19, 38, 39, 11, 1, 32, 25, 35,
             8, 17, 7, 9, 4, 2, 34, 10, 3,
         ];
-        let r = total_permutations(&data);
+        let r = arrangements::<u128>(&data, 3);
+
+        assert_eq!(Some(19208), r);
+        assert_eq!(arrangements::<u128>(&DATA, 3), Some(8));
+    }
+
+    /// Tries every subset of adapters to see which chain up, only good for small bags
+    fn brute_force(jolts: &[u32], max_step: u32) -> u64 {
+        let mut sorted = jolts.to_vec();
+        sorted.sort_unstable();
+        let device = sorted.last().copied().unwrap_or(0) + max_step;
+        (0..1u32 << sorted.len())
+            .filter(|mask| {
+                let mut chain = vec![0];
+                chain.extend(
+                    (0..sorted.len())
+                        .filter(|i| mask & (1 << i) != 0)
+                        .map(|i| sorted[i]),
+                );
+                chain.push(device);
+                chain
+                    .windows(2)
+                    .all(|w| w[1] > w[0] && w[1] - w[0] <= max_step)
+            })
+            .count() as u64
+    }
 
-        assert_eq!(19208, r);
+    #[test]
+    fn arrangements_match_brute_force() {
+        let mut rng = XorShift::new(0x1010);
+        for max_step in 1..=4 {
+            for _ in 0..40 {
+                let len = rng.below(12);
+                // gaps of every size, and now and again the same rating twice
+                let mut jolt = 0;
+                let bag: Vec<u32> = (0..len)
+                    .map(|_| {
+                        jolt += rng.below(max_step as usize + 1) as u32;
+                        jolt.max(1)
+                    })
+                    .collect();
+                assert_eq!(
+                    arrangements::<u64>(&bag, max_step),
+                    Some(brute_force(&bag, max_step)),
+                    "{:?} with steps up to {}",
+                    bag,
+                    max_step
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn long_runs_and_two_jolt_gaps() {
+        // a run of ten 1-jolt gaps used to fall off the end of the lookup table
+        let run: Vec<u32> = (1..=10).collect();
+        assert_eq!(arrangements::<u64>(&run, 3), Some(brute_force(&run, 3)));
+        assert_eq!(
+            arrangements::<u64>(&[2, 4, 6, 7], 3),
+            Some(brute_force(&[2, 4, 6, 7], 3))
+        );
+        assert_eq!(arrangements::<u64>(&[1, 1], 3), Some(2));
+        assert_eq!(arrangements::<u64>(&[5], 3), Some(0));
+        assert_eq!(arrangements::<u64>(&[], 3), Some(1));
+        assert_eq!(arrangements::<u64>(&[2, 4], 1), Some(0));
+    }
+
+    #[test]
+    fn huge_bags_need_big_counts() {
+        let bag: Vec<u32> = (1..=300).collect();
+        assert_eq!(arrangements::<u128>(&bag, 3), None);
+        let big: BigUint = arrangements(&bag, 3).unwrap();
+        // tribonacci, give or take where it starts
+        let mut t = [
+            BigUint::from(1u32),
+            BigUint::from(1u32),
+            BigUint::from(2u32),
+        ];
+        for _ in 3..=300 {
+            let next = &t[0] + &t[1] + &t[2];
+            t = [t[1].clone(), t[2].clone(), next];
+        }
+        assert_eq!(big, t[2]);
+        // smaller ones agree across count types
+        let bag: Vec<u32> = (1..=70).collect();
+        let small = arrangements::<u128>(&bag, 3).unwrap();
+        assert_eq!(arrangements::<BigUint>(&bag, 3), Some(BigUint::from(small)));
+        assert!(arrangements::<u64>(&(1..=100).collect::<Vec<u32>>(), 10).is_none());
+        // too many ways to get half way doesn't matter if there's no getting past that
+        let mut gap: Vec<u32> = (1..=100).collect();
+        gap.extend(&[200, 201, 203]);
+        assert_eq!(arrangements::<u64>(&gap, 10), Some(0));
+    }
+
+    #[test]
+    fn distribution_uses_the_same_device() {
+        let dist = get_distribution(&[1, 2], 5);
+        assert_eq!(dist.get(&1), Some(&2));
+        assert_eq!(dist.get(&5), Some(&1));
+        assert_eq!(dist.get(&3), None);
     }
 }
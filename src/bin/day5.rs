@@ -1,35 +1,103 @@
-use num::clamp;
-use std::cmp::Eq;
-use std::hash::Hash;
-use std::io::{BufRead, Read};
-use std::{
-    collections::HashMap,
-    io::{stdin, BufReader},
-    str::FromStr,
-};
-
-const MAX_ROW: u8 = 128;
-const MAX_COL: u8 = 8;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::{stdin, BufReader};
+use std::{fmt, ops::RangeInclusive};
+use thiserror::Error;
 
-#[derive(Debug, Eq, PartialEq)]
-struct SeatLocation {
-    row: u8,
-    column: u8,
-    seat_id: u16,
-}
-
-impl SeatLocation {
-    fn new<T: AsRef<str>>(s: T) -> Self {
-        let s = s.as_ref();
-        assert_eq!(s.len(), 10);
-        let row = char_to_bin(&s[0..7], 'F', 'B');
-        let column = char_to_bin(&s[7..10], 'L', 'R');
-        SeatLocation {
-            row,
-            column,
-            seat_id: row as u16 * MAX_COL as u16 + column as u16,
+const ROW_LOW: char = 'F';
+const ROW_HIGH: char = 'B';
+const COL_LOW: char = 'L';
+const COL_HIGH: char = 'R';
+
+#[derive(Error, Debug, Eq, PartialEq)]
+enum PassError {
+    #[error("boarding pass `{pass}` should be {expected} characters long")]
+    WrongLength { pass: String, expected: usize },
+    #[error("seat ({row}, {column}) doesn't fit on this plane")]
+    NoSuchSeat { row: u32, column: u32 },
+    #[error("boarding pass `{pass}` has a strange rune `{found}` at position {position}")]
+    BadCharacter {
+        pass: String,
+        position: usize,
+        found: char,
+    },
+    #[error("boarding pass `{pass}` is for row {row}, but the plane only has {rows}")]
+    RowOutOfRange { pass: String, row: u32, rows: u32 },
+    #[error("couldn't read line: {0}")]
+    Unreadable(String),
+}
+
+/// How many characters of a boarding pass pick the row, and how many pick the column.
+/// Every character is one bit, so a plane has up to `2^row_bits` rows of `2^column_bits`
+/// seats. Some planes are shorter than that, so `rows` can cut it off early.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct PlaneLayout {
+    row_bits: u32,
+    column_bits: u32,
+    rows: u32,
+}
+
+impl Default for PlaneLayout {
+    /// The plane from the puzzle: 128 rows, 8 seats across
+    fn default() -> Self {
+        PlaneLayout {
+            row_bits: 7,
+            column_bits: 3,
+            rows: 128,
+        }
+    }
+}
+
+impl PlaneLayout {
+    fn new(row_bits: u32, column_bits: u32) -> Self {
+        assert!(row_bits + column_bits < 32, "seat ids have to fit in a u32");
+        PlaneLayout {
+            row_bits,
+            column_bits,
+            rows: 1 << row_bits,
         }
     }
+
+    /// Only the first `rows` rows actually exist
+    fn with_rows(self, rows: u32) -> Self {
+        assert!(
+            rows <= 1 << self.row_bits,
+            "not enough bits for that many rows"
+        );
+        PlaneLayout { rows, ..self }
+    }
+
+    fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    fn columns(&self) -> u32 {
+        1 << self.column_bits
+    }
+
+    fn pass_len(&self) -> usize {
+        (self.row_bits + self.column_bits) as usize
+    }
+
+    fn seats(&self) -> u32 {
+        self.rows() * self.columns()
+    }
+
+    fn seat_id(&self, row: u32, column: u32) -> u32 {
+        row * self.columns() + column
+    }
+
+    /// The inverse of `seat_id`, gives back `(row, column)`
+    fn position(&self, seat_id: u32) -> (u32, u32) {
+        (seat_id / self.columns(), seat_id % self.columns())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct SeatLocation {
+    row: u32,
+    column: u32,
+    seat_id: u32,
 }
 
 impl std::cmp::Ord for SeatLocation {
@@ -40,40 +108,275 @@ impl std::cmp::Ord for SeatLocation {
 
 impl std::cmp::PartialOrd for SeatLocation {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.seat_id.partial_cmp(&other.seat_id)
+        Some(self.cmp(other))
     }
 }
 
+/// Turns boarding pass strings into seats and back again, for a given plane
+#[derive(Debug, Default, Clone, Copy)]
+struct BoardingPass {
+    layout: PlaneLayout,
+}
 
-fn char_to_bin(s: &str, low: char, high: char) -> u8 {
+impl BoardingPass {
+    fn new(layout: PlaneLayout) -> Self {
+        BoardingPass { layout }
+    }
+
+    fn decode(&self, s: &str) -> Result<SeatLocation, PassError> {
+        if s.chars().count() != self.layout.pass_len() {
+            return Err(PassError::WrongLength {
+                pass: s.to_string(),
+                expected: self.layout.pass_len(),
+            });
+        }
+        let bad_character = |(position, found)| PassError::BadCharacter {
+            pass: s.to_string(),
+            position,
+            found,
+        };
+        let split = s
+            .char_indices()
+            .nth(self.layout.row_bits as usize)
+            .map_or(s.len(), |(i, _)| i);
+        let (row, column) = s.split_at(split);
+        let row = char_to_bin(row, ROW_LOW, ROW_HIGH).map_err(bad_character)?;
+        let column = char_to_bin(column, COL_LOW, COL_HIGH)
+            .map_err(|(position, found)| (position + self.layout.row_bits as usize, found))
+            .map_err(bad_character)?;
+        if row >= self.layout.rows() {
+            return Err(PassError::RowOutOfRange {
+                pass: s.to_string(),
+                row,
+                rows: self.layout.rows(),
+            });
+        }
+        Ok(SeatLocation {
+            row,
+            column,
+            seat_id: self.layout.seat_id(row, column),
+        })
+    }
+
+    fn encode(&self, row: u32, column: u32) -> Result<String, PassError> {
+        if row >= self.layout.rows() || column >= self.layout.columns() {
+            return Err(PassError::NoSuchSeat { row, column });
+        }
+        let mut out = bin_to_chars(row, self.layout.row_bits, ROW_LOW, ROW_HIGH);
+        out.push_str(&bin_to_chars(
+            column,
+            self.layout.column_bits,
+            COL_LOW,
+            COL_HIGH,
+        ));
+        Ok(out)
+    }
+}
+
+/// A boarding pass for a seat somebody else already has
+#[derive(Debug, Eq, PartialEq)]
+struct Duplicate {
+    seat_id: u32,
+    first_line: usize,
+    line: usize,
+}
+
+/// Everything that came out of a pile of boarding passes. Line numbers start at 1.
+#[derive(Debug, Default)]
+struct LoadSummary {
+    seats: Vec<SeatLocation>,
+    duplicates: Vec<Duplicate>,
+    invalid: Vec<(usize, PassError)>,
+}
+
+/// Reads one boarding pass per line, carrying on past any that are broken or
+/// have been seen before. Blank lines are skipped.
+fn load_passes(codec: &BoardingPass, reader: &mut dyn BufRead) -> LoadSummary {
+    let mut summary = LoadSummary::default();
+    let mut seen: HashMap<u32, usize> = HashMap::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                summary
+                    .invalid
+                    .push((line_no, PassError::Unreadable(e.to_string())));
+                // a line that isn't utf-8 is skippable, anything else means the reader is done
+                if e.kind() == std::io::ErrorKind::InvalidData {
+                    continue;
+                }
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match codec.decode(line) {
+            Ok(seat) => match seen.get(&seat.seat_id) {
+                Some(&first_line) => summary.duplicates.push(Duplicate {
+                    seat_id: seat.seat_id,
+                    first_line,
+                    line: line_no,
+                }),
+                None => {
+                    seen.insert(seat.seat_id, line_no);
+                    summary.seats.push(seat);
+                }
+            },
+            Err(e) => summary.invalid.push((line_no, e)),
+        }
+    }
+    summary
+}
+
+/// Which seats on a plane somebody has a boarding pass for
+#[derive(Debug)]
+struct SeatMap {
+    layout: PlaneLayout,
+    taken: Vec<bool>,
+}
+
+impl SeatMap {
+    fn new<'a, I>(layout: PlaneLayout, seats: I) -> Self
+    where
+        I: IntoIterator<Item = &'a SeatLocation>,
+    {
+        let mut taken = vec![false; layout.seats() as usize];
+        for seat in seats {
+            taken[seat.seat_id as usize] = true;
+        }
+        SeatMap { layout, taken }
+    }
+
+    /// Every unoccupied seat id, bunched up into runs of consecutive seats
+    fn free_runs(&self) -> Vec<RangeInclusive<u32>> {
+        let mut runs = Vec::new();
+        let mut start = None;
+        for (id, taken) in self.taken.iter().enumerate() {
+            let id = id as u32;
+            match (start, taken) {
+                (None, false) => start = Some(id),
+                (Some(first), true) => {
+                    runs.push(first..=id - 1);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(first) = start {
+            runs.push(first..=self.layout.seats() - 1);
+        }
+        runs
+    }
+
+    /// The free seat with somebody sitting on either side of it. That'd be ours.
+    fn my_seat(&self) -> Option<u32> {
+        self.free_runs()
+            .into_iter()
+            .filter(|run| run.start() == run.end())
+            .map(|run| *run.start())
+            .find(|&id| id > 0 && id < self.layout.seats() - 1)
+    }
+}
+
+impl fmt::Display for SeatMap {
+    /// One line per row, `#` for a taken seat and `.` for a free one
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.layout.rows().saturating_sub(1).to_string().len();
+        for (row, seats) in self
+            .taken
+            .chunks(self.layout.columns() as usize)
+            .enumerate()
+        {
+            let seats: String = seats
+                .iter()
+                .map(|&taken| if taken { '#' } else { '.' })
+                .collect();
+            writeln!(f, "{:>width$} {}", row, seats, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+/// On strange runes, gives back which character it was and where
+fn char_to_bin(s: &str, low: char, high: char) -> Result<u32, (usize, char)> {
     let mut out = 0;
-    for c in s.chars() {
+    for (i, c) in s.chars().enumerate() {
         // using the F/B example, if we have F, push a 0
         // if we have B, push a 1
         match c {
             _ if c == low => out <<= 1,
             _ if c == high => out = out << 1 | 1,
-            _ => panic!("found a string with strange runes"),
+            _ => return Err((i, c)),
         }
     }
-    out
+    Ok(out)
+}
+
+/// The inverse of `char_to_bin`, most significant bit first
+fn bin_to_chars(value: u32, bits: u32, low: char, high: char) -> String {
+    (0..bits)
+        .rev()
+        .map(|bit| if value >> bit & 1 == 1 { high } else { low })
+        .collect()
 }
 
 fn main() {
-    let r = BufReader::new(stdin());
-    let mut seats: Vec<_> = r.lines()
-        .filter_map(Result::ok)
-        .map(SeatLocation::new)
-        .collect();
-    seats.sort_unstable();
+    // `--map` draws the whole plane, and planes that aren't the one from
+    // the puzzle can be described with `day5 <row bits> <column bits> [<rows>]`
+    let mut show_map = false;
+    let mut args = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--map" => show_map = true,
+            s => args.push(s.parse::<u32>().expect("plane sizes are numbers of bits")),
+        }
+    }
+    let layout = match args[..] {
+        [] => PlaneLayout::default(),
+        [row_bits, column_bits] => PlaneLayout::new(row_bits, column_bits),
+        [row_bits, column_bits, rows] => PlaneLayout::new(row_bits, column_bits).with_rows(rows),
+        _ => panic!("usage: day5 [--map] [<row bits> <column bits> [<rows>]]"),
+    };
+    let codec = BoardingPass::new(layout);
 
-    for seats in (&seats).windows(2) {
-        let this = seats[0].seat_id;
-        let next = seats[1].seat_id;
-        if next - this == 2 {
-            println!("missing seat: {}", this + 1);
+    let mut r = BufReader::new(stdin());
+    let summary = load_passes(&codec, &mut r);
+    for (line, e) in summary.invalid.iter() {
+        eprintln!("line {}: {}", line, e);
+    }
+    for dupe in summary.duplicates.iter() {
+        eprintln!(
+            "line {}: seat {} was already taken on line {}",
+            dupe.line, dupe.seat_id, dupe.first_line
+        );
+    }
+    let map = SeatMap::new(layout, &summary.seats);
+
+    if show_map {
+        print!("{}", map);
+    }
+    for run in map.free_runs() {
+        if run.start() == run.end() {
+            println!("free: {}", run.start());
+        } else {
+            println!(
+                "free: {}-{} ({} seats)",
+                run.start(),
+                run.end(),
+                run.end() - run.start() + 1
+            );
         }
     }
+    match map.my_seat() {
+        Some(id) => {
+            let (row, column) = layout.position(id);
+            println!("my seat: {} ({})", id, codec.encode(row, column).unwrap());
+        }
+        None => println!("couldn't find a seat between two others"),
+    }
 }
 
 #[cfg(test)]
@@ -89,7 +392,7 @@ mod tests {
         ];
         for (case, answer) in known.iter() {
             let row = &case[0..7];
-            assert_eq!(char_to_bin(row, 'F', 'B'), *answer);
+            assert_eq!(char_to_bin(row, 'F', 'B'), Ok(*answer));
         }
     }
 
@@ -103,7 +406,7 @@ mod tests {
         for (case, answer) in known.iter() {
             let row = &case[7..10];
             assert_eq!(case.len(), 10);
-            assert_eq!(char_to_bin(row, 'L', 'R'), *answer);
+            assert_eq!(char_to_bin(row, 'L', 'R'), Ok(*answer));
         }
     }
 
@@ -120,7 +423,196 @@ mod tests {
                 column: *column,
                 seat_id: *seat_id,
             };
-            assert_eq!(SeatLocation::new(case), rhs);
+            assert_eq!(BoardingPass::default().decode(case), Ok(rhs));
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let codec = BoardingPass::default();
+        assert_eq!(codec.encode(70, 7), Ok("BFFFBBFRRR".to_string()));
+        assert_eq!(codec.encode(14, 7), Ok("FFFBBBFRRR".to_string()));
+        assert_eq!(codec.encode(102, 4), Ok("BBFFBBFRLL".to_string()));
+        assert_eq!(
+            codec.encode(128, 0),
+            Err(PassError::NoSuchSeat {
+                row: 128,
+                column: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_wrong_length() {
+        let codec = BoardingPass::default();
+        assert_eq!(
+            codec.decode("BFFFBBFRR"),
+            Err(PassError::WrongLength {
+                pass: "BFFFBBFRR".to_string(),
+                expected: 10
+            })
+        );
+        // it's characters that count, not bytes
+        assert_eq!(
+            codec.decode("BFFFBBFRé"),
+            Err(PassError::WrongLength {
+                pass: "BFFFBBFRé".to_string(),
+                expected: 10
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "seat ids have to fit in a u32")]
+    fn seat_ids_fit_in_a_u32() {
+        PlaneLayout::new(16, 16);
+    }
+
+    #[test]
+    fn test_other_planes() {
+        let codec = BoardingPass::new(PlaneLayout::new(3, 2));
+        let seat = codec.decode("BFBRL").unwrap();
+        assert_eq!(seat.row, 5);
+        assert_eq!(seat.column, 2);
+        assert_eq!(seat.seat_id, 22);
+        assert_eq!(codec.encode(5, 2), Ok("BFBRL".to_string()));
+    }
+
+    #[test]
+    fn every_seat_round_trips() {
+        for &(row_bits, column_bits) in [(7, 3), (3, 2), (0, 4), (5, 0), (10, 6)].iter() {
+            let codec = BoardingPass::new(PlaneLayout::new(row_bits, column_bits));
+            for row in 0..codec.layout.rows() {
+                for column in 0..codec.layout.columns() {
+                    let pass = codec.encode(row, column).unwrap();
+                    let seat = codec.decode(&pass).unwrap();
+                    assert_eq!((seat.row, seat.column), (row, column));
+                    assert_eq!(seat.seat_id, codec.layout.seat_id(row, column));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_pass_round_trips() {
+        let codec = BoardingPass::default();
+        for pass in include_str!("../../inputs/day5.txt").lines() {
+            let seat = codec.decode(pass).unwrap();
+            assert_eq!(codec.encode(seat.row, seat.column), Ok(pass.to_string()));
         }
     }
-}
\ No newline at end of file
+
+    fn seats_from(codec: &BoardingPass, passes: &[&str]) -> Vec<SeatLocation> {
+        passes.iter().map(|p| codec.decode(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_free_runs() {
+        // a plane with 4 rows of 2
+        let codec = BoardingPass::new(PlaneLayout::new(2, 1));
+        let seats = seats_from(&codec, &["FBL", "FBR", "BFR", "BBL"]);
+        let map = SeatMap::new(codec.layout, &seats);
+        assert_eq!(map.free_runs(), vec![0..=1, 4..=4, 7..=7]);
+        assert_eq!(map.my_seat(), Some(4));
+    }
+
+    #[test]
+    fn ends_of_the_plane_arent_my_seat() {
+        let codec = BoardingPass::new(PlaneLayout::new(2, 1));
+        let seats = seats_from(&codec, &["FFR", "FBL", "FBR", "BFL", "BFR", "BBL"]);
+        let map = SeatMap::new(codec.layout, &seats);
+        assert_eq!(map.free_runs(), vec![0..=0, 7..=7]);
+        assert_eq!(map.my_seat(), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let codec = BoardingPass::new(PlaneLayout::new(2, 2));
+        let seats = seats_from(&codec, &["FFLR", "FBRR", "BBLL", "BBLR", "BBRL", "BBRR"]);
+        let map = SeatMap::new(codec.layout, &seats);
+        assert_eq!(map.to_string(), "0 .#..\n1 ...#\n2 ....\n3 ####\n");
+        // a plane with no rows has nothing to draw
+        let empty = SeatMap::new(PlaneLayout::new(2, 2).with_rows(0), &[]);
+        assert_eq!(empty.to_string(), "");
+    }
+
+    #[test]
+    fn finds_my_seat() {
+        let codec = BoardingPass::default();
+        let seats: Vec<_> = include_str!("../../inputs/day5.txt")
+            .lines()
+            .map(|p| codec.decode(p).unwrap())
+            .collect();
+        let map = SeatMap::new(codec.layout, &seats);
+        assert_eq!(map.my_seat(), Some(515));
+    }
+
+    #[test]
+    fn test_bad_characters() {
+        let codec = BoardingPass::default();
+        assert_eq!(
+            codec.decode("BFFXBBFRRR"),
+            Err(PassError::BadCharacter {
+                pass: "BFFXBBFRRR".to_string(),
+                position: 3,
+                found: 'X'
+            })
+        );
+        assert_eq!(
+            codec.decode("BFFFBBFRBR"),
+            Err(PassError::BadCharacter {
+                pass: "BFFFBBFRBR".to_string(),
+                position: 8,
+                found: 'B'
+            })
+        );
+    }
+
+    #[test]
+    fn test_short_plane() {
+        let codec = BoardingPass::new(PlaneLayout::new(2, 1).with_rows(3));
+        assert_eq!(codec.decode("BFR").map(|s| s.seat_id), Ok(5));
+        assert_eq!(
+            codec.decode("BBL"),
+            Err(PassError::RowOutOfRange {
+                pass: "BBL".to_string(),
+                row: 3,
+                rows: 3
+            })
+        );
+        assert!(codec.encode(3, 0).is_err());
+    }
+
+    #[test]
+    fn loader_keeps_going() {
+        let data = "BFFFBBFRRR\n\
+                    FFFBBBFRRR\n\
+                    BFFFBBFRR\n\
+                    BFFFBBFRRR\n\
+                    \n\
+                    BBFFBBFRLQ\n\
+                    BBFFBBFRLL\n";
+        let codec = BoardingPass::default();
+        let summary = load_passes(&codec, &mut std::io::Cursor::new(data));
+        let ids: Vec<_> = summary.seats.iter().map(|s| s.seat_id).collect();
+        assert_eq!(ids, vec![567, 119, 820]);
+        assert_eq!(
+            summary.duplicates,
+            vec![Duplicate {
+                seat_id: 567,
+                first_line: 1,
+                line: 4
+            }]
+        );
+        let bad_lines: Vec<_> = summary.invalid.iter().map(|(line, _)| *line).collect();
+        assert_eq!(bad_lines, vec![3, 6]);
+        assert!(matches!(
+            summary.invalid[1].1,
+            PassError::BadCharacter {
+                position: 9,
+                found: 'Q',
+                ..
+            }
+        ));
+    }
+}
@@ -1,102 +1,411 @@
-use num::clamp;
-use std::cmp::Eq;
-use std::hash::Hash;
-use std::io::{BufRead, Read};
-use std::{
-    collections::HashMap,
-    io::{stdin, BufReader},
-    str::FromStr,
-};
-
-fn anybody_said_yes(batch: &mut dyn BufRead) -> Vec<u32> {
-    let mut out = Vec::new();
-    let mut lines = batch.lines();
-    let mut acc = 0;
-    while let Some(Ok(line)) = lines.next() {
-        if line.is_empty() {
-            out.push(acc);
-            acc = 0;
-        } else {
-            acc |= str_to_bin(line.trim());
+use std::collections::BTreeMap;
+use std::io::{stdin, BufReader};
+use std::io::{BufRead, Write};
+use thiserror::Error;
+
+#[derive(Error, Debug, Eq, PartialEq)]
+enum CustomsError {
+    #[error("line {line}: `{answer}` isn't a question on this form")]
+    UnknownAnswer { answer: char, line: usize },
+    #[error("couldn't read line {line}: {reason}")]
+    Unreadable { line: usize, reason: String },
+}
+
+/// The questions on the customs form, in the order they get a bit in an `AnswerSet`
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Alphabet {
+    symbols: Vec<char>,
+}
+
+impl Default for Alphabet {
+    /// Questions `a` through `z`, like the puzzle
+    fn default() -> Self {
+        Alphabet::new(&('a'..='z').collect::<String>())
+    }
+}
+
+impl Alphabet {
+    fn new(symbols: &str) -> Self {
+        let mut out: Vec<char> = Vec::new();
+        for c in symbols.chars() {
+            if !out.contains(&c) {
+                out.push(c);
+            }
+        }
+        Alphabet { symbols: out }
+    }
+
+    fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    fn index(&self, c: char) -> Option<usize> {
+        self.symbols.iter().position(|s| *s == c)
+    }
+
+    /// Every question answered yes
+    fn all(&self) -> AnswerSet {
+        let mut set = AnswerSet::default();
+        for i in 0..self.len() {
+            set.insert(i);
+        }
+        set
+    }
+
+    /// The symbols for everything in `set`, in alphabet order
+    #[cfg(test)]
+    fn render(&self, set: &AnswerSet) -> String {
+        set.iter().map(|i| self.symbols[i]).collect()
+    }
+
+    /// One person's line of answers. Hands back the first symbol it doesn't know on failure.
+    fn parse(&self, s: &str) -> Result<AnswerSet, char> {
+        let mut set = AnswerSet::default();
+        for c in s.chars() {
+            set.insert(self.index(c).ok_or(c)?);
         }
+        Ok(set)
     }
-    out.push(acc);
+}
+
+const WORD_BITS: usize = 64;
 
-    out
+/// A set of answered questions, one bit per symbol of an `Alphabet`.
+/// Grows a word at a time, so alphabets can be as big as they like.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct AnswerSet {
+    // never has trailing zero words, so `==` works
+    words: Vec<u64>,
 }
 
-fn everybody_said_yes(batch: &mut dyn BufRead) -> Vec<u32> {
-    let mut out = Vec::new();
-    let mut lines = batch.lines();
-    let mut acc = u32::MAX;
-    while let Some(Ok(line)) = lines.next() {
-        if line.is_empty() {
-            out.push(acc);
-            acc = u32::MAX;
-        } else {
-            acc &= str_to_bin(line.trim());
+impl AnswerSet {
+    fn insert(&mut self, i: usize) {
+        let word = i / WORD_BITS;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (i % WORD_BITS);
+    }
+
+    fn contains(&self, i: usize) -> bool {
+        self.words
+            .get(i / WORD_BITS)
+            .map_or(false, |w| w >> (i % WORD_BITS) & 1 == 1)
+    }
+
+    fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.words.len() * WORD_BITS).filter(move |i| self.contains(*i))
+    }
+
+    fn zip_with(&self, other: &AnswerSet, f: impl Fn(u64, u64) -> u64) -> AnswerSet {
+        let len = self.words.len().max(other.words.len());
+        let word = |set: &AnswerSet, i| set.words.get(i).cloned().unwrap_or(0);
+        let mut words: Vec<u64> = (0..len).map(|i| f(word(self, i), word(other, i))).collect();
+        while words.last() == Some(&0) {
+            words.pop();
         }
+        AnswerSet { words }
+    }
+
+    fn union(&self, other: &AnswerSet) -> AnswerSet {
+        self.zip_with(other, |a, b| a | b)
+    }
+
+    fn intersection(&self, other: &AnswerSet) -> AnswerSet {
+        self.zip_with(other, |a, b| a & b)
+    }
+
+    fn difference(&self, other: &AnswerSet) -> AnswerSet {
+        self.zip_with(other, |a, b| a & !b)
     }
-    out.push(acc);
+}
 
-    out
+/// Everything about one group's answers, worked out as its lines go by
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct GroupStats {
+    size: usize,
+    anybody: AnswerSet,
+    everybody: AnswerSet,
+    /// How many people said yes, indexed like the alphabet
+    counts: Vec<usize>,
 }
 
+impl GroupStats {
+    fn new(alphabet: &Alphabet) -> Self {
+        GroupStats {
+            size: 0,
+            anybody: AnswerSet::default(),
+            everybody: alphabet.all(),
+            counts: vec![0; alphabet.len()],
+        }
+    }
 
+    fn add_person(&mut self, answers: &AnswerSet) {
+        self.size += 1;
+        self.anybody = self.anybody.union(answers);
+        self.everybody = self.everybody.intersection(answers);
+        for i in answers.iter() {
+            self.counts[i] += 1;
+        }
+    }
 
-fn main() {
-    let mut r = BufReader::new(stdin());
-    let mut buf = Vec::new();
-    r.read_to_end(&mut buf).unwrap();
-    use std::io::Cursor;
-    let anybody = anybody_said_yes(&mut Cursor::new(&buf));
-    let everybody = everybody_said_yes(&mut Cursor::new(&buf));
+    /// Questions at least `k` people in the group said yes to. Nobody has to say
+    /// yes for `k` of 0, so that's every question on the form.
+    fn at_least(&self, k: usize) -> AnswerSet {
+        let mut set = AnswerSet::default();
+        for (i, count) in self.counts.iter().enumerate() {
+            if *count >= k {
+                set.insert(i);
+            }
+        }
+        set
+    }
+}
+
+/// Walks a batch of customs forms, handing out one group at a time.
+/// Groups are separated by blank lines, and empty groups are skipped.
+struct Groups<'a> {
+    lines: std::iter::Enumerate<std::io::Lines<&'a mut dyn BufRead>>,
+    alphabet: &'a Alphabet,
+}
+
+fn groups<'a>(batch: &'a mut dyn BufRead, alphabet: &'a Alphabet) -> Groups<'a> {
+    Groups {
+        lines: batch.lines().enumerate(),
+        alphabet,
+    }
+}
 
-    let anybody_yes: u32 = anybody
-        .iter()
-        .map(|i| i.count_ones())
-        .sum();
+impl Iterator for Groups<'_> {
+    type Item = Result<GroupStats, CustomsError>;
 
-    let everybody_yes: u32 = everybody
-        .iter()
-        .map(|i| i.count_ones())
-        .sum();
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut group = GroupStats::new(self.alphabet);
+        for (i, line) in &mut self.lines {
+            let line_no = i + 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    return Some(Err(CustomsError::Unreadable {
+                        line: line_no,
+                        reason: e.to_string(),
+                    }))
+                }
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                if group.size > 0 {
+                    return Some(Ok(group));
+                }
+                continue;
+            }
+            match self.alphabet.parse(line) {
+                Ok(answers) => group.add_person(&answers),
+                Err(answer) => {
+                    return Some(Err(CustomsError::UnknownAnswer {
+                        answer,
+                        line: line_no,
+                    }))
+                }
+            }
+        }
+        if group.size > 0 {
+            Some(Ok(group))
+        } else {
+            None
+        }
+    }
+}
 
-    println!("anybody: {}, everybody: {}", anybody_yes, everybody_yes);
+/// Running totals over every group in a batch
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Totals {
+    groups: usize,
+    anybody: usize,
+    everybody: usize,
+    not_everybody: usize,
+    no_common_ground: usize,
+    at_least: usize,
 }
 
-fn char_to_bin(c: char) -> u32 {
-    c.to_digit(36).unwrap() - 10
+impl Totals {
+    fn add(&mut self, group: &GroupStats, k: Option<usize>) {
+        self.groups += 1;
+        self.anybody += group.anybody.len();
+        self.everybody += group.everybody.len();
+        self.not_everybody += group.anybody.difference(&group.everybody).len();
+        if group.everybody.is_empty() {
+            self.no_common_ground += 1;
+        }
+        if let Some(k) = k {
+            self.at_least += group.at_least(k).len();
+        }
+    }
 }
 
-fn str_to_bin(s: &str) -> u32 {
-    s.chars()
-        .map(char_to_bin)
-        .fold(0, |init, c| {
-            init | (1 << c)
-        })
+/// How the survey report gets printed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ReportFormat {
+    Table,
+    Csv,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Per-question tallies and group sizes across a whole batch, for the survey analysts
+#[derive(Debug, Eq, PartialEq)]
+struct Report {
+    symbols: Vec<char>,
+    /// Groups where anybody said yes, indexed like the alphabet
+    anybody: Vec<usize>,
+    /// Groups where everybody said yes, indexed like the alphabet
+    everybody: Vec<usize>,
+    /// Group size to how many groups were that big
+    group_sizes: BTreeMap<usize, usize>,
+}
 
-    #[test]
-    fn test_str_to_bin() {
-        assert_eq!(str_to_bin("abcd"), 0b1111);
-        assert_eq!(str_to_bin("ace"), 0b10101);
-        assert_eq!(str_to_bin("abz"), 0b10000000000000000000000011);
+impl Report {
+    fn new(alphabet: &Alphabet) -> Self {
+        Report {
+            symbols: alphabet.symbols.clone(),
+            anybody: vec![0; alphabet.len()],
+            everybody: vec![0; alphabet.len()],
+            group_sizes: BTreeMap::new(),
+        }
     }
 
-    #[test]
-    fn test_char_to_bin() {
-        assert_eq!(char_to_bin('a'), 0);
-        assert_eq!(char_to_bin('z'), 25);
+    fn add(&mut self, group: &GroupStats) {
+        for i in group.anybody.iter() {
+            self.anybody[i] += 1;
+        }
+        for i in group.everybody.iter() {
+            self.everybody[i] += 1;
+        }
+        *self.group_sizes.entry(group.size).or_insert(0) += 1;
     }
 
-    #[test]
-    fn test_anybody_said_yes() {
-        let data = "abc
+    fn write(&self, format: ReportFormat, w: &mut dyn Write) -> std::io::Result<()> {
+        match format {
+            ReportFormat::Table => self.write_table(w),
+            ReportFormat::Csv => self.write_csv(w),
+        }
+    }
+
+    fn write_table(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(w, "{:<8} {:>8} {:>9}", "question", "anybody", "everybody")?;
+        for (i, symbol) in self.symbols.iter().enumerate() {
+            writeln!(
+                w,
+                "{:<8} {:>8} {:>9}",
+                symbol, self.anybody[i], self.everybody[i]
+            )?;
+        }
+        writeln!(w)?;
+        writeln!(w, "{:<10} {:>6}", "group size", "groups")?;
+        for (size, count) in self.group_sizes.iter() {
+            writeln!(w, "{:<10} {:>6}", size, count)?;
+        }
+        Ok(())
+    }
+
+    /// One table for both halves of the report, `kind` says which a row is from
+    /// and the columns that don't go with it are left empty
+    fn write_csv(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(w, "kind,key,anybody,everybody,groups")?;
+        for (i, symbol) in self.symbols.iter().enumerate() {
+            let symbol = match symbol {
+                ',' | '"' => format!("\"{}\"", symbol.to_string().replace('"', "\"\"")),
+                _ => symbol.to_string(),
+            };
+            writeln!(
+                w,
+                "question,{},{},{},",
+                symbol, self.anybody[i], self.everybody[i]
+            )?;
+        }
+        for (size, count) in self.group_sizes.iter() {
+            writeln!(w, "group_size,{},,,{}", size, count)?;
+        }
+        Ok(())
+    }
+}
+
+fn main() {
+    // `day6 [--alphabet <questions>] [--at-least <k>] [--report table|csv]`
+    let mut alphabet = Alphabet::default();
+    let mut at_least = None;
+    let mut report_format = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--alphabet" => alphabet = Alphabet::new(&args.next().expect("missing alphabet")),
+            "--at-least" => {
+                let k = args.next().and_then(|k| k.parse::<usize>().ok());
+                at_least = Some(
+                    k.filter(|k| *k > 0)
+                        .expect("--at-least needs a number bigger than 0"),
+                );
+            }
+            "--report" => {
+                report_format = match args.next().as_deref() {
+                    Some("table") => Some(ReportFormat::Table),
+                    Some("csv") => Some(ReportFormat::Csv),
+                    _ => panic!("--report is either `table` or `csv`"),
+                }
+            }
+            _ => {
+                panic!("usage: day6 [--alphabet <questions>] [--at-least <k>] [--report table|csv]")
+            }
+        }
+    }
+
+    let mut r = BufReader::new(stdin());
+    let mut totals = Totals::default();
+    let mut report = Report::new(&alphabet);
+    for group in groups(&mut r, &alphabet) {
+        match group {
+            Ok(group) => {
+                totals.add(&group, at_least);
+                report.add(&group);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(format) = report_format {
+        report
+            .write(format, &mut std::io::stdout())
+            .expect("couldn't write the report");
+        return;
+    }
+
+    println!(
+        "anybody: {}, everybody: {}",
+        totals.anybody, totals.everybody
+    );
+    println!(
+        "somebody but not everybody: {}, groups that agreed on nothing: {}",
+        totals.not_everybody, totals.no_common_ground
+    );
+    if let Some(k) = at_least {
+        println!("at least {}: {}", k, totals.at_least);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "abc
 
                         a
                         b
@@ -111,30 +420,186 @@ mod tests {
                         a
 
                         b\n";
-        let mut buf = std::io::Cursor::new(data); 
-        let results = anybody_said_yes(&mut buf);
-        assert_eq!(results, vec![0b111, 0b111, 0b111, 0b1, 0b10]);
+
+    fn rendered(sets: Vec<AnswerSet>) -> Vec<String> {
+        let alphabet = Alphabet::default();
+        sets.iter().map(|s| alphabet.render(s)).collect()
+    }
+
+    #[test]
+    fn test_parse_answers() {
+        let alphabet = Alphabet::default();
+        let indices = |s| alphabet.parse(s).unwrap().iter().collect::<Vec<_>>();
+        assert_eq!(indices("abcd"), vec![0, 1, 2, 3]);
+        assert_eq!(indices("ace"), vec![0, 2, 4]);
+        assert_eq!(indices("abz"), vec![0, 1, 25]);
+    }
+
+    #[test]
+    fn test_symbol_index() {
+        let alphabet = Alphabet::default();
+        assert_eq!(alphabet.index('a'), Some(0));
+        assert_eq!(alphabet.index('z'), Some(25));
+        assert_eq!(alphabet.index('A'), None);
+        assert_eq!(alphabet.parse("abC"), Err('C'));
+    }
+
+    #[test]
+    fn test_big_alphabets() {
+        // 26 + 26 + 10 + a few, too many for a u32 or even a u64
+        let symbols: String = ('a'..='z')
+            .chain('A'..='Z')
+            .chain('0'..='9')
+            .chain("!?#$%&".chars())
+            .collect();
+        let alphabet = Alphabet::new(&symbols);
+        assert_eq!(alphabet.len(), 68);
+
+        let lhs = alphabet.parse("aZ9&").unwrap();
+        let rhs = alphabet.parse("Z&!").unwrap();
+        assert_eq!(lhs.len(), 4);
+        assert!(lhs.contains(67));
+        assert_eq!(alphabet.render(&lhs.union(&rhs)), "aZ9!&");
+        assert_eq!(alphabet.render(&lhs.intersection(&rhs)), "Z&");
+        assert_eq!(alphabet.render(&lhs.difference(&rhs)), "a9");
+        assert_eq!(rhs.difference(&rhs), AnswerSet::default());
+        assert!(lhs.difference(&alphabet.all()).is_empty());
+    }
+
+    fn read_groups(data: &str, alphabet: &Alphabet) -> Result<Vec<GroupStats>, CustomsError> {
+        let mut buf = std::io::Cursor::new(data);
+        groups(&mut buf, alphabet).collect()
+    }
+
+    #[test]
+    fn test_at_least() {
+        let alphabet = Alphabet::default();
+        let group = &read_groups("abc\nab\nbd", &alphabet).unwrap()[0];
+        let at_least = |k| alphabet.render(&group.at_least(k));
+        assert_eq!(at_least(0), alphabet.render(&alphabet.all()));
+        assert_eq!(at_least(1), "abcd");
+        assert_eq!(at_least(2), "ab");
+        assert_eq!(at_least(3), "b");
+        assert_eq!(at_least(4), "");
+    }
+
+    #[test]
+    fn test_group_stats() {
+        let alphabet = Alphabet::new("abcd");
+        let group = &read_groups("abc\nab\nbd", &alphabet).unwrap()[0];
+        assert_eq!(group.size, 3);
+        assert_eq!(group.counts, vec![2, 3, 1, 1]);
+        assert_eq!(alphabet.render(&group.anybody), "abcd");
+        assert_eq!(alphabet.render(&group.everybody), "b");
+    }
+
+    #[test]
+    fn test_anybody_said_yes() {
+        let groups = read_groups(DATA, &Alphabet::default()).unwrap();
+        let results = groups.into_iter().map(|g| g.anybody).collect();
+        assert_eq!(rendered(results), vec!["abc", "abc", "abc", "a", "b"]);
     }
 
     #[test]
     fn test_everybody_said_yes() {
-        let data = "abc
+        let groups = read_groups(DATA, &Alphabet::default()).unwrap();
+        let results = groups.into_iter().map(|g| g.everybody).collect();
+        assert_eq!(rendered(results), vec!["abc", "", "a", "a", "b"]);
+    }
 
-                        a
-                        b
-                        c
+    #[test]
+    fn test_at_least_k_said_yes() {
+        let groups = read_groups(DATA, &Alphabet::default()).unwrap();
+        let results = groups.into_iter().map(|g| g.at_least(2)).collect();
+        assert_eq!(rendered(results), vec!["", "", "a", "a", ""]);
+    }
 
-                        ab
-                        ac
+    #[test]
+    fn extra_blank_lines_arent_groups() {
+        let groups = read_groups("\nab\n\n\n\nb\n\n", &Alphabet::default()).unwrap();
+        let sizes: Vec<_> = groups.iter().map(|g| g.size).collect();
+        assert_eq!(sizes, vec![1, 1]);
+    }
 
-                        a
-                        a
-                        a
-                        a
+    #[test]
+    fn test_totals() {
+        let mut totals = Totals::default();
+        for group in read_groups(DATA, &Alphabet::default()).unwrap() {
+            totals.add(&group, Some(2));
+        }
+        assert_eq!(
+            totals,
+            Totals {
+                groups: 5,
+                anybody: 11,
+                everybody: 6,
+                not_everybody: 5,
+                no_common_ground: 1,
+                at_least: 2,
+            }
+        );
+    }
 
-                        b\n";
-        let mut buf = std::io::Cursor::new(data); 
-        let results = everybody_said_yes(&mut buf);
-        assert_eq!(results, vec![0b111, 0, 0b1, 0b1, 0b10]);
+    #[test]
+    fn unknown_answers_have_line_numbers() {
+        let data = "abc\n\nab\naB\n";
+        assert_eq!(
+            read_groups(data, &Alphabet::default()),
+            Err(CustomsError::UnknownAnswer {
+                answer: 'B',
+                line: 4
+            })
+        );
+        let results = read_groups(data, &Alphabet::new("abcB")).unwrap();
+        assert_eq!(results.len(), 2);
     }
-}
\ No newline at end of file
+
+    fn report_for(data: &str, alphabet: &Alphabet) -> Report {
+        let mut report = Report::new(alphabet);
+        for group in read_groups(data, alphabet).unwrap() {
+            report.add(&group);
+        }
+        report
+    }
+
+    #[test]
+    fn test_report() {
+        let report = report_for(DATA, &Alphabet::new("abcd"));
+        assert_eq!(report.anybody, vec![4, 4, 3, 0]);
+        assert_eq!(report.everybody, vec![3, 2, 1, 0]);
+        let sizes: Vec<_> = report.group_sizes.into_iter().collect();
+        assert_eq!(sizes, vec![(1, 2), (2, 1), (3, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn test_report_csv() {
+        let report = report_for("ab\nb\n\na,\n", &Alphabet::new("ab,"));
+        let mut out = Vec::new();
+        report.write(ReportFormat::Csv, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "kind,key,anybody,everybody,groups\n\
+             question,a,2,1,\n\
+             question,b,1,1,\n\
+             question,\",\",1,1,\n\
+             group_size,1,,,1\n\
+             group_size,2,,,1\n"
+        );
+    }
+
+    #[test]
+    fn test_report_table() {
+        let report = report_for("ab\nb\n", &Alphabet::new("ab"));
+        let mut out = Vec::new();
+        report.write(ReportFormat::Table, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "question  anybody everybody\n\
+             a               1         0\n\
+             b               1         1\n\
+             \n\
+             group size groups\n\
+             2               1\n"
+        );
+    }
+}
@@ -1,12 +1,17 @@
 use daggy::{Dag, NodeIndex};
+use std::io::{BufRead, Write};
 use std::{
     collections::{HashMap, HashSet},
     io::{stdin, BufReader},
 };
-use std::io::BufRead;
+use thiserror::Error;
 
 type IndexMap = HashMap<String, NodeIndex>;
 type BagDag = Dag<String, u32>;
+/// `day7 <subcommand> <args...>`
+type Subcommand = fn(&Bags, &[String]) -> Result<(), String>;
+/// A colour and what it holds, straight out of a rule line
+type Rule<'a> = (&'a str, Vec<(u32, &'a str)>);
 
 #[derive(Debug)]
 struct Contains(u32);
@@ -15,88 +20,583 @@ struct Contains(u32);
 struct Bags {
     indices: IndexMap,
     dag: BagDag,
+    duplicates: Vec<DuplicateRule>,
+}
+
+#[derive(Error, Debug, Eq, PartialEq)]
+enum RuleError {
+    #[error("line {line}: bags would end up inside themselves: {}", .path.join(" -> "))]
+    Cycle { line: usize, path: Vec<String> },
+    #[error("line {line}: rule for {colour} bags disagrees with the one on line {first_line}")]
+    Contradiction {
+        colour: String,
+        first_line: usize,
+        line: usize,
+    },
+    #[error("line {line}: {source}")]
+    Parse { line: usize, source: ParseError },
+    #[error("couldn't read line {line}: {reason}")]
+    Unreadable { line: usize, reason: String },
+}
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+enum ParseError {
+    #[error("rules need a ` contain ` between the bag and what it holds")]
+    MissingContain,
+    #[error("`{0}` should be a colour followed by `bag` or `bags`")]
+    NotABag(String),
+    #[error("`{0}` should start with how many bags, and that can't be zero")]
+    BadCount(String),
+    #[error("nothing between a pair of commas")]
+    EmptyItem,
+    #[error("{0} bags show up more than once in the same rule")]
+    SameColourTwice(String),
+}
+
+fn children_of(idx: NodeIndex, dag: &BagDag) -> Vec<NodeIndex> {
+    use daggy::Walker;
+    dag.children(idx).iter(dag).map(|(_e, n)| n).collect()
+}
+
+/// `idx` and every bag inside it, outer bags always before the bags they hold.
+/// Keeps its own stack, so deeply nested rules can't blow ours.
+fn descendants_in_order(idx: NodeIndex, dag: &BagDag) -> Vec<NodeIndex> {
+    let mut seen = HashSet::new();
+    let mut finished = Vec::new();
+    // each entry is a bag, and the children of it we haven't gone into yet
+    let mut stack = vec![(idx, children_of(idx, dag))];
+    seen.insert(idx);
+    while let Some((node, children)) = stack.last_mut() {
+        let node = *node;
+        match children.pop() {
+            Some(child) => {
+                if seen.insert(child) {
+                    stack.push((child, children_of(child, dag)));
+                }
+            }
+            None => {
+                finished.push(node);
+                stack.pop();
+            }
+        }
+    }
+    // post-order puts the innermost bags first, flip it
+    finished.reverse();
+    finished
 }
 
 fn all_ancestors(idx: NodeIndex, dag: &BagDag) -> HashSet<NodeIndex> {
     use daggy::Walker;
     let mut set = HashSet::new();
-    let parent_iter = dag.parents(idx);
-    for (_edge, node) in parent_iter.iter(&dag) {
-        set.insert(node);
-        set.extend(all_ancestors(node, &dag));
+    let mut to_visit = vec![idx];
+    while let Some(current) = to_visit.pop() {
+        for (_edge, node) in dag.parents(current).iter(dag) {
+            if set.insert(node) {
+                to_visit.push(node);
+            }
+        }
     }
     set
 }
 
-fn bags_contained_by(idx: NodeIndex, dag: &BagDag) -> u32 {
+/// None if there are more than a u64 can count, big rule files get silly fast
+fn bags_contained_by(idx: NodeIndex, dag: &BagDag) -> Option<u64> {
+    all_contents(idx, dag)?
+        .values()
+        .try_fold(0u64, |total, count| total.checked_add(*count))
+}
+
+/// Every bag inside `idx`, however deeply, and how many of it there'd be in total,
+/// or None if any of those is too many for a u64.
+/// Each bag gets visited once, after everything that could be holding it.
+fn all_contents(idx: NodeIndex, dag: &BagDag) -> Option<HashMap<NodeIndex, u64>> {
     use daggy::Walker;
-    let children = dag.children(idx);
-    let mut count = 0;
-    for (e, n) in children.iter(&dag) {
-        let number_of_bags = *dag.edge_weight(e).unwrap();
-        count += number_of_bags + number_of_bags * bags_contained_by(n, &dag);
+    let mut contents = HashMap::new();
+    contents.insert(idx, 1u64);
+    for node in descendants_in_order(idx, dag) {
+        let outer = contents[&node];
+        for (e, n) in dag.children(node).iter(dag) {
+            let number_of_bags = *dag.edge_weight(e).unwrap() as u64;
+            let count = contents.entry(n).or_insert(0);
+            *count = count.checked_add(outer.checked_mul(number_of_bags)?)?;
+        }
     }
-    count
+    contents.remove(&idx);
+    Some(contents)
 }
 
-fn parse_line(line: &str) -> (&str, Vec<(u32, &str)>) {
-    lazy_static::lazy_static! {
-        static ref HANDLER: regex::Regex = regex::Regex::new(r"(\d+) (\w+ \w+) bags?").unwrap();
-    };
+/// Fewest bags nested between `idx` and a bag `is_end` likes, both ends included.
+/// Chains are at least one bag deep, so a bag never ends a chain on its own.
+fn shortest_chain(
+    idx: NodeIndex,
+    dag: &BagDag,
+    is_end: impl Fn(NodeIndex) -> bool,
+) -> Option<Vec<NodeIndex>> {
+    use daggy::Walker;
+    use std::collections::VecDeque;
+    // plain old BFS, remembering how we got everywhere
+    let mut came_from: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(idx);
+    while let Some(current) = queue.pop_front() {
+        for (_e, n) in dag.children(current).iter(dag) {
+            if came_from.contains_key(&n) {
+                continue;
+            }
+            came_from.insert(n, current);
+            if is_end(n) {
+                let mut chain = vec![n];
+                let mut cursor = n;
+                while cursor != idx {
+                    cursor = came_from[&cursor];
+                    chain.push(cursor);
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            queue.push_back(n);
+        }
+    }
+    None
+}
 
-    let name_len = line
-        .split_whitespace()
-        .take(2)
-        .collect::<Vec<&str>>()
-        .join(" ")
-        .len();
-    let mut out = Vec::new();
-    for capture in HANDLER.captures_iter(line) {
-        let count = capture.get(1).unwrap().as_str().parse().unwrap();
-        let name = capture.get(2).unwrap().as_str();
-        out.push((count, name));
+/// Most bags nested between `idx` and a bag `is_end` likes, both ends included
+fn longest_chain(
+    idx: NodeIndex,
+    dag: &BagDag,
+    is_end: &dyn Fn(NodeIndex) -> bool,
+) -> Option<Vec<NodeIndex>> {
+    // innermost bags first, so every bag's children are already worked out.
+    // best[n] is how many more bags the longest chain from n goes, and where it goes next
+    let mut best: HashMap<NodeIndex, (usize, NodeIndex)> = HashMap::new();
+    for node in descendants_in_order(idx, dag).into_iter().rev() {
+        for child in children_of(node, dag) {
+            let through_child = match best.get(&child) {
+                Some((len, _)) => Some(len + 1),
+                None if is_end(child) => Some(1),
+                None => None,
+            };
+            if let Some(len) = through_child {
+                if best.get(&node).map_or(true, |(b, _)| len > *b) {
+                    best.insert(node, (len, child));
+                }
+            }
+        }
+    }
+
+    best.get(&idx)?;
+    let mut chain = vec![idx];
+    let mut cursor = idx;
+    while let Some((_, next)) = best.get(&cursor) {
+        cursor = *next;
+        chain.push(cursor);
     }
+    Some(chain)
+}
 
-    (&line[0..name_len], out)
+/// Whether `outer` could ever end up with an `inner` in it
+fn can_contain(outer: NodeIndex, inner: NodeIndex, dag: &BagDag) -> bool {
+    shortest_chain(outer, dag, |n| n == inner).is_some()
 }
 
-fn build_bagdag(reader: &mut dyn BufRead) -> Bags {
+/// Bags that don't hold anything else, where chains bottom out
+fn is_empty_bag(idx: NodeIndex, dag: &BagDag) -> bool {
+    use daggy::Walker;
+    dag.children(idx).walk_next(dag).is_none()
+}
+
+/// `bright white bags` or `bright white bag` down to just `bright white`
+fn strip_bags(s: &str) -> Option<&str> {
+    let s = s.trim();
+    let colour = s.strip_suffix("bags").or_else(|| s.strip_suffix("bag"))?;
+    // `bag` has to be a word of its own, and there has to be a colour before it
+    if !colour.ends_with(char::is_whitespace) || colour.trim().is_empty() {
+        return None;
+    }
+    Some(colour.trim())
+}
+
+/// Rules look like `<colour> bags contain <n> <colour> bag(s), ...` or
+/// `<colour> bags contain no other bags`. Colours can be any number of words,
+/// `bag` or `bags` are both fine whatever the count, and trailing punctuation is ignored.
+fn parse_line(line: &str) -> Result<Rule<'_>, ParseError> {
+    let line = line.trim_end_matches(|c: char| c.is_whitespace() || ".,;!".contains(c));
+    let (outer, inner) = line
+        .split_once(" contain ")
+        .ok_or(ParseError::MissingContain)?;
+    let name = strip_bags(outer).ok_or_else(|| ParseError::NotABag(outer.trim().to_string()))?;
+
+    let inner = inner.trim();
+    if inner == "no other bags" || inner == "no other bag" {
+        return Ok((name, Vec::new()));
+    }
+
+    let mut out: Vec<(u32, &str)> = Vec::new();
+    for item in inner.split(',').map(str::trim) {
+        if item.is_empty() {
+            return Err(ParseError::EmptyItem);
+        }
+        let (count, rest) = item
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| ParseError::BadCount(item.to_string()))?;
+        let count = match count.parse() {
+            Ok(0) | Err(_) => return Err(ParseError::BadCount(item.to_string())),
+            Ok(count) => count,
+        };
+        let colour = strip_bags(rest).ok_or_else(|| ParseError::NotABag(item.to_string()))?;
+        if out.iter().any(|(_, seen)| *seen == colour) {
+            return Err(ParseError::SameColourTwice(colour.to_string()));
+        }
+        out.push((count, colour));
+    }
+
+    Ok((name, out))
+}
+
+/// Two lines with the exact same rule for one colour. Harmless, but worth a mention.
+#[derive(Debug, Eq, PartialEq)]
+struct DuplicateRule {
+    colour: String,
+    first_line: usize,
+    line: usize,
+}
+
+/// Reads one rule per line. Line numbers in errors start at 1.
+fn build_bagdag(reader: &mut dyn BufRead) -> Result<Bags, RuleError> {
     let mut indices = HashMap::new();
     let mut dag: BagDag = Dag::new();
-    let mut lines = reader.lines();
+    let mut duplicates = Vec::new();
+    // colour -> (line it was described on, what it holds, sorted)
+    let mut rules: HashMap<String, (usize, Vec<(u32, String)>)> = HashMap::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.map_err(|e| RuleError::Unreadable {
+            line: line_no,
+            reason: e.to_string(),
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (name, children) = parse_line(&line).map_err(|source| RuleError::Parse {
+            line: line_no,
+            source,
+        })?;
+
+        let mut rule: Vec<(u32, String)> = children
+            .iter()
+            .map(|(count, child)| (*count, child.to_string()))
+            .collect();
+        rule.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+        if let Some((first_line, first_rule)) = rules.get(name) {
+            if *first_rule != rule {
+                return Err(RuleError::Contradiction {
+                    colour: name.to_string(),
+                    first_line: *first_line,
+                    line: line_no,
+                });
+            }
+            duplicates.push(DuplicateRule {
+                colour: name.to_string(),
+                first_line: *first_line,
+                line: line_no,
+            });
+            continue;
+        }
+        rules.insert(name.to_string(), (line_no, rule));
 
-    while let Some(Ok(line)) = lines.next() {
-        let (name, children) = parse_line(&line);
         let parent_name = name.to_string();
         let parent_index = *indices
             .entry(parent_name)
             .or_insert_with(|| dag.add_node(name.to_string()));
         for (count, child_name) in children {
             let name = child_name.to_string();
-            let child_index = indices
+            let child_index = *indices
                 .entry(name)
                 .or_insert_with(|| dag.add_node(child_name.to_string()));
-            dag.add_edge(parent_index, *child_index, count).unwrap();
+            // daggy only looks for cycles when both ends already have edges, so a
+            // bag holding itself slips past it
+            let would_cycle = parent_index == child_index
+                || dag.add_edge(parent_index, child_index, count).is_err();
+            if would_cycle {
+                // the child can already get back around to the parent, go find out how
+                let mut path = vec![dag[parent_index].clone()];
+                match shortest_chain(child_index, &dag, |n| n == parent_index) {
+                    Some(chain) => path.extend(chain.iter().map(|n| dag[*n].clone())),
+                    None => path.push(dag[child_index].clone()),
+                }
+                return Err(RuleError::Cycle {
+                    line: line_no,
+                    path,
+                });
+            }
+        }
+    }
+
+    Ok(Bags {
+        indices,
+        dag,
+        duplicates,
+    })
+}
+
+impl Bags {
+    fn index(&self, name: &str) -> Result<NodeIndex, String> {
+        self.indices
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("no rules mention a `{}` bag", name))
+    }
+
+    fn name(&self, idx: NodeIndex) -> &str {
+        &self.dag[idx]
+    }
+
+    /// `shiny gold -> 1 dark olive -> 3 faded blue`
+    fn describe_chain(&self, chain: &[NodeIndex]) -> String {
+        let mut out = self.name(chain[0]).to_string();
+        for pair in chain.windows(2) {
+            let edge = self.dag.find_edge(pair[0], pair[1]).unwrap();
+            out.push_str(&format!(" -> {} {}", self.dag[edge], self.name(pair[1])));
+        }
+        out
+    }
+
+    fn sorted_names(&self, indices: impl Iterator<Item = NodeIndex>) -> Vec<&str> {
+        let mut names: Vec<_> = indices.map(|idx| self.name(idx)).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// `day7 query <colour> [<other colour>]`
+fn query(bags: &Bags, args: &[String]) -> Result<(), String> {
+    let (colour, other) = match args {
+        [colour] => (colour, None),
+        [colour, other] => (colour, Some(other)),
+        _ => return Err("usage: day7 query <colour> [<other colour>]".to_string()),
+    };
+    let idx = bags.index(colour)?;
+
+    if let Some(other) = other {
+        let other_idx = bags.index(other)?;
+        if !can_contain(idx, other_idx, &bags.dag) {
+            println!("{} bags can never hold a {} bag", colour, other);
+            return Ok(());
+        }
+        println!("{} bags can hold {} bags", colour, other);
+        let is_other = |n| n == other_idx;
+        let shortest = shortest_chain(idx, &bags.dag, is_other).unwrap();
+        let longest = longest_chain(idx, &bags.dag, &is_other).unwrap();
+        println!("shortest chain: {}", bags.describe_chain(&shortest));
+        println!("longest chain: {}", bags.describe_chain(&longest));
+        return Ok(());
+    }
+
+    let ancestors = all_ancestors(idx, &bags.dag);
+    println!("{} kinds of bag can hold a {} bag", ancestors.len(), colour);
+    for name in bags.sorted_names(ancestors.into_iter()) {
+        println!("  {}", name);
+    }
+
+    match (
+        all_contents(idx, &bags.dag),
+        bags_contained_by(idx, &bags.dag),
+    ) {
+        (Some(contents), Some(total)) => {
+            println!("{} bags hold {} bags in total", colour, total);
+            for name in bags.sorted_names(contents.keys().cloned()) {
+                println!("  {} {}", contents[&bags.indices[name]], name);
+            }
         }
+        _ => println!("{} bags hold too many bags to count", colour),
     }
 
-    Bags { indices, dag }
+    let is_empty = |n| is_empty_bag(n, &bags.dag);
+    if let Some(shortest) = shortest_chain(idx, &bags.dag, is_empty) {
+        let longest = longest_chain(idx, &bags.dag, &is_empty).unwrap();
+        println!("shortest chain: {}", bags.describe_chain(&shortest));
+        println!("longest chain: {}", bags.describe_chain(&longest));
+    }
+    Ok(())
+}
+
+/// Knobs for `write_dot`
+#[derive(Debug, Default)]
+struct ExportOptions {
+    /// Colour in this bag, everything that can hold it and everything it holds
+    highlight: Option<NodeIndex>,
+    /// Only write out this bag and what's inside it
+    root: Option<NodeIndex>,
+}
+
+/// Graphviz for the rules, with how many of each bag on the edges
+fn write_dot(bags: &Bags, options: &ExportOptions, w: &mut dyn Write) -> std::io::Result<()> {
+    use daggy::petgraph::visit::EdgeRef;
+    let mut nodes: Vec<NodeIndex> = match options.root {
+        Some(root) => descendants_in_order(root, &bags.dag),
+        None => bags.dag.graph().node_indices().collect(),
+    };
+    nodes.sort_unstable();
+    let included: HashSet<NodeIndex> = nodes.iter().cloned().collect();
+
+    let (ancestors, descendants) = match options.highlight {
+        Some(idx) => (
+            all_ancestors(idx, &bags.dag),
+            descendants_in_order(idx, &bags.dag)
+                .into_iter()
+                .filter(|&n| n != idx)
+                .collect(),
+        ),
+        None => (HashSet::new(), HashSet::new()),
+    };
+
+    let quoted = |idx: NodeIndex| format!("\"{}\"", bags.name(idx).replace('"', "\\\""));
+    writeln!(w, "digraph bags {{")?;
+    for &idx in nodes.iter() {
+        let fill = if Some(idx) == options.highlight {
+            Some("gold")
+        } else if ancestors.contains(&idx) {
+            Some("lightblue")
+        } else if descendants.contains(&idx) {
+            Some("palegreen")
+        } else {
+            None
+        };
+        match fill {
+            Some(fill) => writeln!(w, "    {} [style=filled, fillcolor={}];", quoted(idx), fill)?,
+            None => writeln!(w, "    {};", quoted(idx))?,
+        }
+    }
+    for edge in bags.dag.graph().edge_references() {
+        if !included.contains(&edge.source()) || !included.contains(&edge.target()) {
+            continue;
+        }
+        writeln!(
+            w,
+            "    {} -> {} [label=\"{}\"];",
+            quoted(edge.source()),
+            quoted(edge.target()),
+            edge.weight()
+        )?;
+    }
+    writeln!(w, "}}")
+}
+
+/// `day7 export <file> [--highlight <colour>] [--root <colour>]`
+fn export(bags: &Bags, args: &[String]) -> Result<(), String> {
+    let usage = || "usage: day7 export <file> [--highlight <colour>] [--root <colour>]".to_string();
+    let mut args = args.iter();
+    let path = args.next().ok_or_else(usage)?;
+    let mut options = ExportOptions::default();
+    while let Some(arg) = args.next() {
+        let colour = args.next().ok_or_else(usage)?;
+        match arg.as_str() {
+            "--highlight" => options.highlight = Some(bags.index(colour)?),
+            "--root" => options.root = Some(bags.index(colour)?),
+            _ => return Err(usage()),
+        }
+    }
+
+    let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut w = std::io::BufWriter::new(file);
+    write_dot(bags, &options, &mut w)
+        .and_then(|_| w.flush())
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+/// `1 shiny gold bag` or `2 shiny gold bags`
+fn bag_count(count: u32, colour: &str) -> String {
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {} bag{}", count, colour, plural)
+}
+
+/// The rule for `idx` the way we'd have written it, with what it holds sorted by colour
+fn write_rule(bags: &Bags, idx: NodeIndex) -> String {
+    use daggy::Walker;
+    let mut contents: Vec<(&str, u32)> = bags
+        .dag
+        .children(idx)
+        .iter(&bags.dag)
+        .map(|(e, n)| (bags.name(n), bags.dag[e]))
+        .collect();
+    if contents.is_empty() {
+        return format!("{} bags contain no other bags.", bags.name(idx));
+    }
+    contents.sort_unstable();
+    let contents: Vec<String> = contents
+        .into_iter()
+        .map(|(colour, count)| bag_count(count, colour))
+        .collect();
+    format!("{} bags contain {}.", bags.name(idx), contents.join(", "))
+}
+
+/// Every rule, sorted by colour. Bags that only ever showed up inside other
+/// bags get a `no other bags` rule of their own.
+fn write_rules(bags: &Bags, w: &mut dyn Write) -> std::io::Result<()> {
+    let mut colours: Vec<(&str, NodeIndex)> = bags
+        .indices
+        .iter()
+        .map(|(name, idx)| (name.as_str(), *idx))
+        .collect();
+    colours.sort_unstable();
+    for (_, idx) in colours {
+        writeln!(w, "{}", write_rule(bags, idx))?;
+    }
+    Ok(())
+}
+
+/// `day7 normalise`, prints the rules back out in canonical form
+fn normalise(bags: &Bags, args: &[String]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("usage: day7 normalise".to_string());
+    }
+    let stdout = std::io::stdout();
+    write_rules(bags, &mut stdout.lock()).map_err(|e| e.to_string())
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
     let mut r = BufReader::new(stdin());
-    let bags = build_bagdag(&mut r);
+    let bags = match build_bagdag(&mut r) {
+        Ok(bags) => bags,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    for dupe in bags.duplicates.iter() {
+        eprintln!(
+            "line {}: {} bags were already described on line {}",
+            dupe.line, dupe.colour, dupe.first_line
+        );
+    }
+
+    let subcommand: Option<Subcommand> = match args.first().map(String::as_str) {
+        Some("query") => Some(query),
+        Some("export") => Some(export),
+        Some("normalise") => Some(normalise),
+        _ => None,
+    };
+    if let Some(subcommand) = subcommand {
+        if let Err(e) = subcommand(&bags, &args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // part 1 - how many shiny gold options are there
     let idx = bags.indices.get("shiny gold").unwrap();
     let ancestors = all_ancestors(*idx, &bags.dag);
     // part 2 - how much does a shiny gold hold?
     let bag_count = bags_contained_by(*idx, &bags.dag);
 
-    use daggy::petgraph::dot::{Config, Dot};
-    println!("{:?}", Dot::with_config(&bags.dag, &[]));
-
     println!("total: {}", ancestors.len());
-    println!("shiny gold contains {} bags", bag_count);
+    match bag_count {
+        Some(count) => println!("shiny gold contains {} bags", count),
+        None => println!("shiny gold contains too many bags to count"),
+    }
 }
 
 #[cfg(test)]
@@ -116,7 +616,7 @@ dotted black bags contain no other bags.";
     #[test]
     fn parse_one_line() {
         let line = "striped crimson bags contain 2 shiny gold bags, 4 pale indigo bags, 4 light maroon bags.";
-        let (name, contains) = parse_line(line);
+        let (name, contains) = parse_line(line).unwrap();
         assert_eq!(name, "striped crimson");
         assert_eq!(
             contains,
@@ -131,7 +631,7 @@ dotted black bags contain no other bags.";
     #[test]
     fn test_ancestor_lookup() {
         let mut reader = std::io::Cursor::new(DATA);
-        let stuff = build_bagdag(&mut reader);
+        let stuff = build_bagdag(&mut reader).unwrap();
         let idx = *stuff.indices.get("shiny gold").unwrap();
         let total = all_ancestors(idx, &stuff.dag);
         assert_eq!(4, total.len());
@@ -140,12 +640,12 @@ dotted black bags contain no other bags.";
     #[test]
     fn test_child_lookup() {
         let mut reader = std::io::Cursor::new(DATA);
-        let stuff = build_bagdag(&mut reader);
+        let stuff = build_bagdag(&mut reader).unwrap();
 
         let idx = *stuff.indices.get("shiny gold").unwrap();
         let total = bags_contained_by(idx, &stuff.dag);
 
-        assert_eq!(32, total);
+        assert_eq!(Some(32), total);
     }
 
     #[test]
@@ -158,14 +658,374 @@ dark green bags contain 2 dark blue bags.
 dark blue bags contain 2 dark violet bags.
 dark violet bags contain no other bags.";
         let mut reader = std::io::Cursor::new(data);
-        let stuff = build_bagdag(&mut reader);
+        let stuff = build_bagdag(&mut reader).unwrap();
 
         let idx = *stuff.indices.get("shiny gold").unwrap();
         let total = bags_contained_by(idx, &stuff.dag);
 
-        assert_eq!(126, total);
+        assert_eq!(Some(126), total);
     }
 
     #[test]
     fn test_build_map_from_strings() {}
+
+    fn example() -> Bags {
+        let mut reader = std::io::Cursor::new(DATA);
+        build_bagdag(&mut reader).unwrap()
+    }
+
+    #[test]
+    fn test_all_contents() {
+        let bags = example();
+        let idx = bags.index("shiny gold").unwrap();
+        let contents = all_contents(idx, &bags.dag).unwrap();
+        let mut named: Vec<_> = contents
+            .iter()
+            .map(|(n, count)| (bags.name(*n), *count))
+            .collect();
+        named.sort_unstable();
+        assert_eq!(
+            named,
+            vec![
+                ("dark olive", 1),
+                ("dotted black", 16),
+                ("faded blue", 13),
+                ("vibrant plum", 2),
+            ]
+        );
+        assert_eq!(
+            Some(contents.values().sum::<u64>()),
+            bags_contained_by(idx, &bags.dag)
+        );
+    }
+
+    #[test]
+    fn test_chains() {
+        let bags = example();
+        let light_red = bags.index("light red").unwrap();
+        let faded_blue = bags.index("faded blue").unwrap();
+        let is_blue = |n| n == faded_blue;
+
+        let shortest = shortest_chain(light_red, &bags.dag, is_blue).unwrap();
+        assert_eq!(
+            bags.describe_chain(&shortest),
+            "light red -> 2 muted yellow -> 9 faded blue"
+        );
+        let longest = longest_chain(light_red, &bags.dag, &is_blue).unwrap();
+        assert_eq!(longest.len(), 5);
+        assert_eq!(bags.name(longest[2]), "shiny gold");
+
+        let is_empty = |n| is_empty_bag(n, &bags.dag);
+        let shortest = shortest_chain(faded_blue, &bags.dag, is_empty);
+        assert_eq!(shortest, None);
+    }
+
+    #[test]
+    fn test_can_contain() {
+        let bags = example();
+        let idx = |name| bags.index(name).unwrap();
+        assert!(can_contain(
+            idx("light red"),
+            idx("dotted black"),
+            &bags.dag
+        ));
+        assert!(can_contain(idx("shiny gold"), idx("faded blue"), &bags.dag));
+        assert!(!can_contain(
+            idx("faded blue"),
+            idx("shiny gold"),
+            &bags.dag
+        ));
+        assert!(!can_contain(
+            idx("shiny gold"),
+            idx("shiny gold"),
+            &bags.dag
+        ));
+        assert!(!can_contain(
+            idx("light red"),
+            idx("dark orange"),
+            &bags.dag
+        ));
+        assert!(bags.index("plaid tartan").is_err());
+    }
+
+    /// Rungs of a ladder, every bag holding the next two down. Walking every
+    /// path through this takes about as long as the heat death of the universe.
+    fn ladder(rungs: usize) -> String {
+        let mut out = String::new();
+        for i in 0..rungs {
+            let line = match rungs - i {
+                1 => format!("tone{} blue bags contain no other bags.\n", i),
+                2 => format!("tone{} blue bags contain 1 tone{} blue bag.\n", i, i + 1),
+                _ => format!(
+                    "tone{} blue bags contain 1 tone{} blue bag, 2 tone{} blue bags.\n",
+                    i,
+                    i + 1,
+                    i + 2
+                ),
+            };
+            out.push_str(&line);
+        }
+        out
+    }
+
+    #[test]
+    fn big_ladders_dont_take_forever() {
+        let rungs = 20_000;
+        let mut reader = std::io::Cursor::new(ladder(rungs));
+        let bags = build_bagdag(&mut reader).unwrap();
+        let top = bags.index("tone0 blue").unwrap();
+        let bottom = bags.index(&format!("tone{} blue", rungs - 1)).unwrap();
+
+        assert_eq!(all_ancestors(bottom, &bags.dag).len(), rungs - 1);
+        assert_eq!(descendants_in_order(top, &bags.dag).len(), rungs);
+        assert_eq!(all_contents(top, &bags.dag), None);
+        assert_eq!(bags_contained_by(top, &bags.dag), None);
+
+        let is_bottom = |n| n == bottom;
+        let longest = longest_chain(top, &bags.dag, &is_bottom).unwrap();
+        assert_eq!(longest.len(), rungs);
+        let shortest = shortest_chain(top, &bags.dag, is_bottom).unwrap();
+        assert_eq!(shortest.len(), rungs / 2 + 1);
+    }
+
+    #[test]
+    fn small_ladders_add_up() {
+        // 3 holds nothing, 2 holds one 3, 1 holds a 2 and two 3s, 0 holds a 1 and two 2s
+        let mut reader = std::io::Cursor::new(ladder(4));
+        let bags = build_bagdag(&mut reader).unwrap();
+        let top = bags.index("tone0 blue").unwrap();
+        assert_eq!(bags_contained_by(top, &bags.dag), Some(9));
+    }
+
+    fn load(data: &str) -> Result<Bags, RuleError> {
+        build_bagdag(&mut std::io::Cursor::new(data))
+    }
+
+    #[test]
+    fn cycles_name_the_loop() {
+        let data = "light red bags contain 1 bright white bag.
+bright white bags contain 2 muted yellow bags.
+muted yellow bags contain 3 light red bags.";
+        assert_eq!(
+            load(data).unwrap_err(),
+            RuleError::Cycle {
+                line: 3,
+                path: vec![
+                    "muted yellow".to_string(),
+                    "light red".to_string(),
+                    "bright white".to_string(),
+                    "muted yellow".to_string(),
+                ]
+            }
+        );
+
+        let data = "faded blue bags contain 1 faded blue bag.";
+        let err = load(data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: bags would end up inside themselves: faded blue -> faded blue"
+        );
+    }
+
+    #[test]
+    fn duplicate_rules_get_flagged() {
+        let data = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain no other bags.
+
+light red bags contain 2 muted yellow bags, 1 bright white bag.";
+        let bags = load(data).unwrap();
+        assert_eq!(
+            bags.duplicates,
+            vec![DuplicateRule {
+                colour: "light red".to_string(),
+                first_line: 1,
+                line: 4
+            }]
+        );
+        let idx = bags.index("light red").unwrap();
+        assert_eq!(bags_contained_by(idx, &bags.dag), Some(3));
+    }
+
+    #[test]
+    fn contradicting_rules_are_errors() {
+        let data = "light red bags contain 1 bright white bag.
+bright white bags contain no other bags.
+light red bags contain 2 bright white bags.";
+        assert_eq!(
+            load(data).unwrap_err(),
+            RuleError::Contradiction {
+                colour: "light red".to_string(),
+                first_line: 1,
+                line: 3
+            }
+        );
+    }
+
+    fn dot_for(bags: &Bags, options: &ExportOptions) -> String {
+        let mut out = Vec::new();
+        write_dot(bags, options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_export_everything() {
+        let bags = example();
+        let dot = dot_for(&bags, &ExportOptions::default());
+        assert!(dot.starts_with("digraph bags {\n"));
+        assert!(dot.contains("    \"light red\";\n"));
+        assert!(dot.contains("    \"muted yellow\" -> \"faded blue\" [label=\"9\"];\n"));
+        assert_eq!(dot.matches(" -> ").count(), 13);
+        assert!(!dot.contains("fillcolor"));
+    }
+
+    #[test]
+    fn test_export_highlight() {
+        let bags = example();
+        let options = ExportOptions {
+            highlight: Some(bags.index("shiny gold").unwrap()),
+            root: None,
+        };
+        let dot = dot_for(&bags, &options);
+        assert!(dot.contains("    \"shiny gold\" [style=filled, fillcolor=gold];\n"));
+        assert!(dot.contains("    \"dark orange\" [style=filled, fillcolor=lightblue];\n"));
+        assert!(dot.contains("    \"dotted black\" [style=filled, fillcolor=palegreen];\n"));
+        assert_eq!(dot.matches("fillcolor").count(), 9);
+    }
+
+    #[test]
+    fn test_export_subgraph() {
+        let bags = example();
+        let options = ExportOptions {
+            highlight: None,
+            root: Some(bags.index("shiny gold").unwrap()),
+        };
+        let dot = dot_for(&bags, &options);
+        assert!(!dot.contains("light red"));
+        assert!(!dot.contains("muted yellow"));
+        assert!(dot.contains("    \"shiny gold\" -> \"vibrant plum\" [label=\"2\"];\n"));
+        assert_eq!(dot.lines().count(), 2 + 5 + 6);
+    }
+
+    #[test]
+    fn parse_multi_word_colours() {
+        let line = "light sky blue bags contain 1 red bag, 12 dark olive green bags.";
+        assert_eq!(
+            parse_line(line),
+            Ok(("light sky blue", vec![(1, "red"), (12, "dark olive green")]))
+        );
+        let line = "red bag contain 2 blue bag";
+        assert_eq!(parse_line(line), Ok(("red", vec![(2, "blue")])));
+    }
+
+    #[test]
+    fn parse_empty_bags() {
+        for line in [
+            "faded blue bags contain no other bags.",
+            "faded blue bags contain no other bags",
+            "faded blue bag contain no other bag. ",
+            "faded blue bags contain no other bags!",
+        ]
+        .iter()
+        {
+            assert_eq!(parse_line(line), Ok(("faded blue", vec![])), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            (
+                "faded blue bags hold 2 red bags.",
+                ParseError::MissingContain,
+            ),
+            (
+                "faded blue contain 2 red bags.",
+                ParseError::NotABag("faded blue".to_string()),
+            ),
+            (
+                "bags contain 2 red bags.",
+                ParseError::NotABag("bags".to_string()),
+            ),
+            (
+                "faded blue bags contain two red bags.",
+                ParseError::BadCount("two red bags".to_string()),
+            ),
+            (
+                "faded blue bags contain 0 red bags.",
+                ParseError::BadCount("0 red bags".to_string()),
+            ),
+            (
+                "faded blue bags contain 2 red baggage.",
+                ParseError::NotABag("2 red baggage".to_string()),
+            ),
+            (
+                "faded blue bags contain 2 red bags,, 1 tan bag.",
+                ParseError::EmptyItem,
+            ),
+            (
+                "faded blue bags contain 2 red bags, 1 red bag.",
+                ParseError::SameColourTwice("red".to_string()),
+            ),
+        ];
+        for (line, error) in cases.iter() {
+            assert_eq!(parse_line(line), Err(error.clone()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parse_errors_have_line_numbers() {
+        let data = "light red bags contain 1 bright white bag.
+bright white bags contain 1 shiny.";
+        assert_eq!(
+            load(data).unwrap_err().to_string(),
+            "line 2: `1 shiny` should be a colour followed by `bag` or `bags`"
+        );
+    }
+
+    fn canonical(bags: &Bags) -> String {
+        let mut out = Vec::new();
+        write_rules(bags, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_rules() {
+        let data = "light red bags contain 2 muted yellow bags, 1 bright white bag.
+bright white bag contain 1 shiny gold bags
+muted yellow bags contain no other bags.";
+        assert_eq!(
+            canonical(&load(data).unwrap()),
+            "bright white bags contain 1 shiny gold bag.
+light red bags contain 1 bright white bag, 2 muted yellow bags.
+muted yellow bags contain no other bags.
+shiny gold bags contain no other bags.
+"
+        );
+    }
+
+    #[test]
+    fn input_round_trips() {
+        let data = include_str!("../../inputs/day7.txt");
+        let bags = load(data).unwrap();
+        let text = canonical(&bags);
+        assert_eq!(text.lines().count(), data.lines().count());
+
+        // same rules as the input, line for line, just tidied up
+        fn rules(text: &str) -> Vec<Rule<'_>> {
+            let mut rules: Vec<_> = text
+                .lines()
+                .map(|line| {
+                    let (name, mut contents) = parse_line(line).unwrap();
+                    contents.sort_unstable_by_key(|(_, colour)| *colour);
+                    (name, contents)
+                })
+                .collect();
+            rules.sort_unstable();
+            rules
+        }
+        assert_eq!(rules(&text), rules(data));
+
+        // and writing it out again doesn't change anything
+        assert_eq!(canonical(&load(&text).unwrap()), text);
+    }
 }
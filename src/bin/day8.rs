@@ -1,100 +1,782 @@
-use std::io::BufRead;
-use std::io::{stdin, BufReader, Read};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, stdin, BufRead, BufReader, Read, Write};
+use std::{fmt, str::FromStr};
 use thiserror::Error;
 
+#[cfg(test)]
+#[path = "../xorshift.rs"]
+mod xorshift;
+
 #[derive(Error, Debug, Eq, PartialEq)]
 enum CPUError {
     #[error("loop detected at {ip} to {dst}")]
-    WouldLoop { ip: usize, dst: isize },
+    WouldLoop { ip: usize, dst: usize },
+    #[error("gave up after {budget} steps")]
+    OutOfSteps { budget: usize },
+    #[error("{ip} jumps to {dst}, before the start of the program")]
+    JumpBeforeStart { ip: usize, dst: isize },
+    #[error("{ip} jumps to {dst}, past the end of the program")]
+    JumpPastEnd { ip: usize, dst: usize },
+    /// only if ip was pointed somewhere odd by hand, jumps can't get there
     #[error("index out of bounds: {0}")]
     IndexOutOfBounds(usize),
+    #[error("{ip} overflows a register")]
+    Overflow { ip: usize },
+    #[error("line {line}: {source}")]
+    Parse { line: usize, source: ParseError },
+}
+
+#[derive(Error, Debug, Eq, PartialEq)]
+enum ParseError {
     #[error("failed to decode string as integer `{0}`")]
     IntegerDecode(String),
     #[error("unknown instruction: `{0}`")]
     UnknownInstruction(String),
+    #[error("unknown register: `{0}`, there's r0 to r7")]
+    UnknownRegister(String),
+    #[error("`{0}` should be an instruction, maybe a register, and an argument")]
+    Malformed(String),
+}
+
+/// How many registers there are, r0 is the accumulator
+const REGISTERS: usize = 8;
+
+/// Everything instructions can change, apart from where we are
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct Registers {
+    r: [isize; REGISTERS],
+    /// where `ret` goes back to
+    lr: usize,
+    /// everything `out` has written, oldest first
+    output: Vec<isize>,
+}
+
+/// What an instruction's argument means for what runs next, as far as
+/// anything looking at the program without running it is concerned
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Flow {
+    /// always the next instruction
+    FallThrough,
+    /// always ip + arg
+    Jump,
+    /// ip + arg or the next instruction, depending on the registers
+    Branch,
+    /// somewhere that depends on the registers
+    Dynamic,
+}
+
+/// What an instruction does. A new opcode is one of these plus an entry in OPERATIONS,
+/// the cpu doesn't need to know about it.
+trait Operation: Sync {
+    fn name(&self) -> &'static str;
+
+    fn flow(&self) -> Flow {
+        Flow::FallThrough
+    }
+
+    /// Where running `ins` at `ip` goes next, worked out before it changes anything.
+    /// Being off either end of the program is for the cpu to worry about.
+    fn next(&self, ip: usize, _ins: Instruction, _regs: &Registers) -> isize {
+        ip as isize + 1
+    }
+
+    /// What running `ins` at `ip` does to the registers. If it fails the
+    /// registers have to be left as they were.
+    fn apply(&self, _ip: usize, _ins: Instruction, _regs: &mut Registers) -> Result<(), CPUError> {
+        Ok(())
+    }
+}
+
+struct Nop;
+impl Operation for Nop {
+    fn name(&self) -> &'static str {
+        "nop"
+    }
+}
+
+/// adds arg to a register
+struct Acc;
+impl Operation for Acc {
+    fn name(&self) -> &'static str {
+        "acc"
+    }
+
+    fn apply(&self, ip: usize, ins: Instruction, regs: &mut Registers) -> Result<(), CPUError> {
+        regs.r[ins.reg] = regs.r[ins.reg]
+            .checked_add(ins.arg)
+            .ok_or(CPUError::Overflow { ip })?;
+        Ok(())
+    }
+}
+
+/// sets a register to arg
+struct Set;
+impl Operation for Set {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    fn apply(&self, _: usize, ins: Instruction, regs: &mut Registers) -> Result<(), CPUError> {
+        regs.r[ins.reg] = ins.arg;
+        Ok(())
+    }
+}
+
+struct Jmp;
+impl Operation for Jmp {
+    fn name(&self) -> &'static str {
+        "jmp"
+    }
+
+    fn flow(&self) -> Flow {
+        Flow::Jump
+    }
+
+    fn next(&self, ip: usize, ins: Instruction, _: &Registers) -> isize {
+        (ip as isize).saturating_add(ins.arg)
+    }
+}
+
+/// jumps if a register is zero
+struct Jz;
+impl Operation for Jz {
+    fn name(&self) -> &'static str {
+        "jz"
+    }
+
+    fn flow(&self) -> Flow {
+        Flow::Branch
+    }
+
+    fn next(&self, ip: usize, ins: Instruction, regs: &Registers) -> isize {
+        if regs.r[ins.reg] == 0 {
+            (ip as isize).saturating_add(ins.arg)
+        } else {
+            ip as isize + 1
+        }
+    }
+}
+
+/// jumps unless a register is zero
+struct Jnz;
+impl Operation for Jnz {
+    fn name(&self) -> &'static str {
+        "jnz"
+    }
+
+    fn flow(&self) -> Flow {
+        Flow::Branch
+    }
+
+    fn next(&self, ip: usize, ins: Instruction, regs: &Registers) -> isize {
+        if regs.r[ins.reg] != 0 {
+            (ip as isize).saturating_add(ins.arg)
+        } else {
+            ip as isize + 1
+        }
+    }
+}
+
+/// jumps, leaving lr pointing after itself for `ret`
+struct Call;
+impl Operation for Call {
+    fn name(&self) -> &'static str {
+        "call"
+    }
+
+    fn flow(&self) -> Flow {
+        Flow::Jump
+    }
+
+    fn next(&self, ip: usize, ins: Instruction, _: &Registers) -> isize {
+        (ip as isize).saturating_add(ins.arg)
+    }
+
+    fn apply(&self, ip: usize, _: Instruction, regs: &mut Registers) -> Result<(), CPUError> {
+        regs.lr = ip + 1;
+        Ok(())
+    }
+}
+
+/// goes back to lr, the argument doesn't matter
+struct Ret;
+impl Operation for Ret {
+    fn name(&self) -> &'static str {
+        "ret"
+    }
+
+    fn flow(&self) -> Flow {
+        Flow::Dynamic
+    }
+
+    fn next(&self, _: usize, _: Instruction, regs: &Registers) -> isize {
+        regs.lr as isize
+    }
+}
+
+/// writes out a register plus arg
+struct Out;
+impl Operation for Out {
+    fn name(&self) -> &'static str {
+        "out"
+    }
+
+    fn apply(&self, ip: usize, ins: Instruction, regs: &mut Registers) -> Result<(), CPUError> {
+        let value = regs.r[ins.reg]
+            .checked_add(ins.arg)
+            .ok_or(CPUError::Overflow { ip })?;
+        regs.output.push(value);
+        Ok(())
+    }
+}
+
+/// Which operation an instruction is. Two opcodes are the same if they have the same name.
+#[derive(Clone, Copy)]
+struct Opcode(&'static dyn Operation);
+
+impl Opcode {
+    const NOP: Opcode = Opcode(&Nop);
+    const ACC: Opcode = Opcode(&Acc);
+    const SET: Opcode = Opcode(&Set);
+    const JMP: Opcode = Opcode(&Jmp);
+    const JZ: Opcode = Opcode(&Jz);
+    const JNZ: Opcode = Opcode(&Jnz);
+    const CALL: Opcode = Opcode(&Call);
+    const RET: Opcode = Opcode(&Ret);
+    const OUT: Opcode = Opcode(&Out);
+
+    fn flow(self) -> Flow {
+        self.0.flow()
+    }
+}
+
+/// Every opcode the parser knows about
+const OPERATIONS: &[Opcode] = &[
+    Opcode::NOP,
+    Opcode::ACC,
+    Opcode::SET,
+    Opcode::JMP,
+    Opcode::JZ,
+    Opcode::JNZ,
+    Opcode::CALL,
+    Opcode::RET,
+    Opcode::OUT,
+];
+
+impl PartialEq for Opcode {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.name() == other.0.name()
+    }
+}
+
+impl Eq for Opcode {}
+
+impl fmt::Debug for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.name())
+    }
+}
+
+impl FromStr for Opcode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OPERATIONS
+            .iter()
+            .find(|op| op.0.name() == s)
+            .copied()
+            .ok_or_else(|| ParseError::UnknownInstruction(s.to_string()))
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.name())
+    }
+}
+
+fn parse_register(s: &str) -> Result<usize, ParseError> {
+    s.strip_prefix('r')
+        .and_then(|n| n.parse().ok())
+        .filter(|n| *n < REGISTERS)
+        .ok_or_else(|| ParseError::UnknownRegister(s.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Instruction {
+    op: Opcode,
+    /// which register it works on, r0 unless it says otherwise
+    reg: usize,
+    arg: isize,
+}
+
+impl Instruction {
+    /// The opcode, and the register if it isn't r0
+    fn head(&self) -> String {
+        match self.reg {
+            0 => self.op.to_string(),
+            reg => format!("{} r{}", self.op, reg),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = ParseError;
+
+    // example instructions:
+    // jmp -4
+    // jnz r2 +3
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (op, reg, arg) = match parts.as_slice() {
+            [op, arg] => (op, 0, arg),
+            [op, reg, arg] => (op, parse_register(reg)?, arg),
+            _ => return Err(ParseError::Malformed(s.trim().to_string())),
+        };
+        let op = op.parse()?;
+        let arg = arg
+            .parse()
+            .map_err(|_| ParseError::IntegerDecode(arg.to_string()))?;
+        Ok(Instruction { op, reg, arg })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:+}", self.head(), self.arg)
+    }
+}
+
+/// One instruction per line, blank lines are skipped. Line numbers in errors start at 1.
+fn parse_program(code: &str) -> Result<Vec<Instruction>, CPUError> {
+    code.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            line.parse().map_err(|source| CPUError::Parse {
+                line: i + 1,
+                source,
+            })
+        })
+        .collect()
+}
+
+#[derive(Error, Debug, Eq, PartialEq)]
+enum AsmError {
+    #[error("line {line}: {source}")]
+    Parse { line: usize, source: ParseError },
+    #[error("line {line}: there's no label called `{label}`")]
+    UnknownLabel { line: usize, label: String },
+    #[error("line {line}: `{label}` was already a label on line {first_line}")]
+    DuplicateLabel {
+        line: usize,
+        label: String,
+        first_line: usize,
+    },
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().map_or(false, |c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Turns friendlier source into a program. On top of plain `op +N` lines it takes
+/// `# comments` and `; comments`, blank lines, and `name:` labels, which any
+/// instruction can use in place of its argument (`jmp loop`) to mean the offset
+/// to that label. A label after the last instruction points at the end of the program.
+fn assemble(source: &str) -> Result<Vec<Instruction>, AsmError> {
+    let mut labels: HashMap<&str, (usize, usize)> = HashMap::new();
+    // line number, opcode, register, argument
+    let mut pending: Vec<(usize, &str, Option<&str>, &str)> = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let mut line = line.split(['#', ';']).next().unwrap().trim();
+        while let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                break;
+            }
+            if let Some((_, first_line)) = labels.insert(label, (pending.len(), line_no)) {
+                return Err(AsmError::DuplicateLabel {
+                    line: line_no,
+                    label: label.to_string(),
+                    first_line,
+                });
+            }
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            [op, arg] => pending.push((line_no, op, None, arg)),
+            [op, reg, arg] => pending.push((line_no, op, Some(reg), arg)),
+            _ => {
+                return Err(AsmError::Parse {
+                    line: line_no,
+                    source: ParseError::Malformed(line.to_string()),
+                })
+            }
+        }
+    }
+
+    let mut program = Vec::new();
+    for (ip, (line, op, reg, arg)) in pending.into_iter().enumerate() {
+        let op = op
+            .parse()
+            .map_err(|source| AsmError::Parse { line, source })?;
+        let reg = reg
+            .map_or(Ok(0), parse_register)
+            .map_err(|source| AsmError::Parse { line, source })?;
+        let arg = match (arg.parse::<isize>(), labels.get(arg)) {
+            (Ok(arg), _) => arg,
+            (Err(_), Some((target, _))) => *target as isize - ip as isize,
+            (Err(_), None) if is_label(arg) => {
+                return Err(AsmError::UnknownLabel {
+                    line,
+                    label: arg.to_string(),
+                })
+            }
+            (Err(_), None) => {
+                return Err(AsmError::Parse {
+                    line,
+                    source: ParseError::IntegerDecode(arg.to_string()),
+                })
+            }
+        };
+        program.push(Instruction { op, reg, arg });
+    }
+    Ok(program)
+}
+
+/// Where `arg` takes you from `ip`, if that's still somewhere in (or the very end of) the program
+fn jump_target(ip: usize, arg: isize, len: usize) -> Option<usize> {
+    let target = (ip as isize).checked_add(arg)?;
+    if target >= 0 && target as usize <= len {
+        Some(target as usize)
+    } else {
+        None
+    }
+}
+
+/// Whether the argument says where to jump to, so it's worth a label
+fn jumps(op: Opcode) -> bool {
+    matches!(op.flow(), Flow::Jump | Flow::Branch)
+}
+
+/// Writes a program back out as `assemble` source, split into basic blocks.
+/// Every block gets a label, jumps go to labels, and comments say where
+/// everything lands. `nop`s say where they'd go if they were a `jmp`.
+fn disassemble(code: &[Instruction]) -> String {
+    let len = code.len();
+    // a block starts at the top, wherever a jump lands, and after anything that
+    // might not fall through
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    for (ip, ins) in code.iter().enumerate() {
+        if jumps(ins.op) {
+            leaders.extend(jump_target(ip, ins.arg, len));
+        }
+        if ins.op.flow() != Flow::FallThrough {
+            leaders.insert(ip + 1);
+        }
+    }
+    let mut labels: HashMap<usize, String> = HashMap::new();
+    for (n, ip) in leaders.into_iter().filter(|ip| *ip < len).enumerate() {
+        labels.insert(ip, format!("block{}", n));
+    }
+    let ends_up_at_end = code
+        .iter()
+        .enumerate()
+        .any(|(ip, ins)| jumps(ins.op) && jump_target(ip, ins.arg, len) == Some(len));
+    if ends_up_at_end {
+        labels.insert(len, "end".to_string());
+    }
+
+    let mut out = String::new();
+    for (ip, ins) in code.iter().enumerate() {
+        if let Some(label) = labels.get(&ip) {
+            out.push_str(&format!("{}:\n", label));
+        }
+        // other arguments aren't offsets, and might not even make sense as one
+        let target = if jumps(ins.op) || ins.op == Opcode::NOP {
+            jump_target(ip, ins.arg, len)
+        } else {
+            None
+        };
+        let (text, comment) = match (ins.op, target) {
+            (op, Some(target)) if jumps(op) => (
+                format!("{} {}", ins.head(), labels[&target]),
+                format!("{} -> {}", ip, target),
+            ),
+            (op, None) if jumps(op) => (ins.to_string(), format!("{} -> outside the program", ip)),
+            (op, Some(target)) if op == Opcode::NOP => {
+                (ins.to_string(), format!("{} -> {} as a jmp", ip, target))
+            }
+            (op, None) if op == Opcode::NOP => {
+                (ins.to_string(), format!("{} -> outside as a jmp", ip))
+            }
+            _ => (ins.to_string(), ip.to_string()),
+        };
+        out.push_str(&format!("    {:<16}; {}\n", text, comment));
+    }
+    if let Some(label) = labels.get(&len) {
+        out.push_str(&format!("{}:\n", label));
+    }
+    out
+}
+
+/// One instruction the cpu got through
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct TraceEntry {
+    ip: usize,
+    ins: Instruction,
+    acc_before: isize,
+    acc_after: isize,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>5}  {:<8} acc {} -> {}",
+            self.ip,
+            self.ins.to_string(),
+            self.acc_before,
+            self.acc_after
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum TraceFormat {
+    Text,
+    JsonLines,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::JsonLines),
+            _ => Err(format!("unknown trace format `{}`, try text or json", s)),
+        }
+    }
+}
+
+/// Everything a cpu ran, in order, if you asked it to keep track
+#[derive(Debug, Default)]
+struct Trace {
+    entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    fn write(&self, format: TraceFormat, out: &mut impl Write) -> io::Result<()> {
+        for entry in &self.entries {
+            match format {
+                TraceFormat::Text => writeln!(out, "{}", entry)?,
+                TraceFormat::JsonLines => writeln!(
+                    out,
+                    r#"{{"ip":{},"op":"{}","reg":{},"arg":{},"acc_before":{},"acc_after":{}}}"#,
+                    entry.ip,
+                    entry.ins.op,
+                    entry.ins.reg,
+                    entry.ins.arg,
+                    entry.acc_before,
+                    entry.acc_after
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The instructions that keep coming round if we're back at `target`:
+    /// everything since `target` last ran.
+    fn cycle(&self, target: usize) -> Option<Vec<usize>> {
+        let start = self.entries.iter().rposition(|e| e.ip == target)?;
+        Some(self.entries[start..].iter().map(|e| e.ip).collect())
+    }
+}
+
+/// When the cpu gives up on a program that isn't going anywhere. Visits are
+/// counted per instruction, with no idea of who called what, so with the first
+/// two a subroutine that gets called more than once looks like a loop. Use a
+/// budget for programs like that.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+enum LoopPolicy {
+    /// stop before anything runs a second time
+    #[default]
+    AnyRevisit,
+    /// let every instruction run this many times, stop before one runs again
+    MaxVisits(usize),
+    /// don't look for loops at all, just stop after this many steps
+    Never { budget: usize },
+}
+
+/// `revisit`, `visits=N` or `budget=N`
+impl FromStr for LoopPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |n: &str| {
+            n.parse()
+                .map_err(|_| format!("`{}` isn't a number in `{}`", n, s))
+        };
+        match s.split_once('=') {
+            None if s == "revisit" => Ok(LoopPolicy::AnyRevisit),
+            Some(("visits", n)) => match number(n)? {
+                0 => Err("visits needs a number bigger than 0".to_string()),
+                n => Ok(LoopPolicy::MaxVisits(n)),
+            },
+            Some(("budget", n)) => Ok(LoopPolicy::Never { budget: number(n)? }),
+            _ => Err(format!(
+                "unknown loop policy `{}`, try revisit, visits=N or budget=N",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct CPU {
-    code: Vec<String>,
+    code: Vec<Instruction>,
     counts: Vec<usize>,
-    acc: isize,
     ip: usize,
-    lr: usize,
+    /// the instruction that ran last, for saying where a loop came back from
+    came_from: Option<usize>,
+    regs: Registers,
+    steps: usize,
+    policy: LoopPolicy,
+    trace: Option<Trace>,
 }
 
 impl CPU {
-    fn new(code: &str) -> CPU {
-        let cursor = std::io::Cursor::new(code);
-        let code: Vec<String> = cursor.lines().filter_map(Result::ok).collect();
+    fn new(code: &str) -> Result<CPU, CPUError> {
+        Ok(CPU::with_program(parse_program(code)?))
+    }
+
+    fn with_program(code: Vec<Instruction>) -> CPU {
         let code_len = code.len();
         CPU {
             code,
             counts: vec![0; code_len],
-            acc: 0,
             ip: 0,
-            lr: 0,
+            came_from: None,
+            regs: Registers::default(),
+            steps: 0,
+            policy: LoopPolicy::default(),
+            trace: None,
         }
     }
 
+    fn with_policy(mut self, policy: LoopPolicy) -> CPU {
+        self.policy = policy;
+        self
+    }
+
+    /// Start keeping a trace of everything that runs from here on
+    fn record(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    fn acc(&self) -> isize {
+        self.regs.r[0]
+    }
+
+    /// Where the instruction at `ip` sends us next, which might not be anywhere real
+    fn destination(&self, ip: usize) -> Option<isize> {
+        let ins = *self.code.get(ip)?;
+        Some(ins.op.0.next(ip, ins, &self.regs))
+    }
+
+    /// If the last step failed with a loop, this is the bit of program that repeats.
+    /// Needs a trace to have been running since before the loop started.
+    fn loop_cycle(&self) -> Option<Vec<usize>> {
+        self.trace.as_ref()?.cycle(self.ip)
+    }
+
+    /// Back to how it was before anything ran, keeping the program, policy and
+    /// whether we're tracing
     fn reset(&mut self) {
-        self.acc = 0;
         self.ip = 0;
-        self.lr = 0;
+        self.came_from = None;
+        self.regs = Registers::default();
+        self.steps = 0;
+        self.counts.iter_mut().for_each(|c| *c = 0);
+        if let Some(trace) = &mut self.trace {
+            trace.entries.clear();
+        }
+    }
+
+    /// Would running something that's already run `visits` times break the loop policy?
+    fn would_loop(&self, visits: usize) -> bool {
+        match self.policy {
+            LoopPolicy::AnyRevisit => visits >= 1,
+            LoopPolicy::MaxVisits(n) => visits >= n,
+            LoopPolicy::Never { .. } => false,
+        }
     }
 
+    /// Runs the instruction at ip. Everything gets checked before anything changes,
+    /// so a step that fails leaves the cpu as it was. That includes the loop check:
+    /// we stop just before an instruction runs more often than the policy allows,
+    /// however we got to it.
     fn step(&mut self) -> Result<bool, CPUError> {
         if self.ip == self.code.len() {
             return Ok(false);
         }
-        let (opcode, arg) = self
+        let ins = *self
             .code
             .get(self.ip)
-            .and_then(|s| {
-                let mut parts = s.trim().split_whitespace();
-                let op = parts.next()?;
-                let arg = parts.next()?;
-                Some((op, arg))
-            })
             .ok_or(CPUError::IndexOutOfBounds(self.ip))?;
+        if let LoopPolicy::Never { budget } = self.policy {
+            if self.steps >= budget {
+                return Err(CPUError::OutOfSteps { budget });
+            }
+        }
+        if self.would_loop(self.counts[self.ip]) {
+            return Err(CPUError::WouldLoop {
+                ip: self.came_from.unwrap_or(self.ip),
+                dst: self.ip,
+            });
+        }
 
-        let arg: isize = arg
-            .parse()
-            .map_err(|_| CPUError::IntegerDecode(arg.to_string()))?;
-
-        let ins_count = self
-            .counts
-            .get_mut(self.ip)
+        let dst = self
+            .destination(self.ip)
             .ok_or(CPUError::IndexOutOfBounds(self.ip))?;
-        *ins_count += 1;
-
-        match opcode {
-            "nop" => {
-                self.ip += 1;
-                Ok(true)
-            }
-            "acc" => {
-                self.acc += arg;
-                self.ip += 1;
-                Ok(true)
-            }
-            "jmp" => {
-                self.lr = self.ip;
-                let new_ip = (self.ip as isize).saturating_add(arg) as usize;
-                if self.counts.get(new_ip).map_or(0, |i| *i) > 0 {
-                    Err(CPUError::WouldLoop {
-                        ip: self.ip,
-                        dst: arg,
-                    })
-                } else if new_ip >= self.code.len() {
-                    Err(CPUError::IndexOutOfBounds(new_ip))
-                } else {
-                    self.ip = new_ip;
-                    Ok(true)
-                }
-            }
-            _ => Err(CPUError::UnknownInstruction(opcode.to_string())),
+        // landing just past the last instruction is how programs finish,
+        // by falling off the end or jumping there
+        if dst < 0 {
+            return Err(CPUError::JumpBeforeStart { ip: self.ip, dst });
+        }
+        let new_ip = dst as usize;
+        if new_ip > self.code.len() {
+            return Err(CPUError::JumpPastEnd {
+                ip: self.ip,
+                dst: new_ip,
+            });
         }
+
+        let (ip, acc_before) = (self.ip, self.acc());
+        ins.op.0.apply(ip, ins, &mut self.regs)?;
+        self.counts[ip] += 1;
+        self.steps += 1;
+        self.ip = new_ip;
+        self.came_from = Some(ip);
+        if let Some(trace) = &mut self.trace {
+            trace.entries.push(TraceEntry {
+                ip,
+                ins,
+                acc_before,
+                acc_after: self.regs.r[0],
+            });
+        }
+        Ok(true)
     }
 
     fn run(&mut self) -> Result<(), CPUError> {
@@ -107,42 +789,635 @@ impl CPU {
     }
 }
 
-fn run_with_swapped(swap: usize, cpu: &mut CPU) -> Option<isize> {
-    // set up swapped instruction
-    let ins = cpu.code.get_mut(swap)?;
-    match &ins[..3] {
-        x @ "nop" => *ins = ins.replace(x, "jmp"),
-        x @ "jmp" => *ins = ins.replace(x, "nop"),
-        _ => {}
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Watch {
+    /// stop whenever acc changes
+    Changes,
+    /// stop when acc turns into this
+    Equals(isize),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Command {
+    Break(usize),
+    Delete(usize),
+    Watch(Watch),
+    Unwatch,
+    Step(usize),
+    Continue,
+    Registers,
+    Counts,
+    Output,
+    List,
+    Reset,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let number = |word: &str| {
+            word.parse()
+                .map_err(|_| format!("`{}` isn't a number", word))
+        };
+        let index = |word: &str| {
+            word.parse()
+                .map_err(|_| format!("`{}` isn't an instruction index", word))
+        };
+        match words.as_slice() {
+            ["break", at] | ["b", at] => Ok(Command::Break(index(at)?)),
+            ["delete", at] | ["d", at] => Ok(Command::Delete(index(at)?)),
+            ["watch", "acc"] | ["w", "acc"] => Ok(Command::Watch(Watch::Changes)),
+            ["watch", "acc", value] | ["w", "acc", value] => {
+                Ok(Command::Watch(Watch::Equals(number(value)?)))
+            }
+            ["unwatch"] => Ok(Command::Unwatch),
+            ["step"] | ["s"] | [] => Ok(Command::Step(1)),
+            ["step", n] | ["s", n] => Ok(Command::Step(index(n)?)),
+            ["continue"] | ["c"] => Ok(Command::Continue),
+            ["regs"] | ["r"] => Ok(Command::Registers),
+            ["counts"] => Ok(Command::Counts),
+            ["output"] | ["o"] => Ok(Command::Output),
+            ["list"] | ["l"] => Ok(Command::List),
+            ["reset"] => Ok(Command::Reset),
+            ["help"] | ["h"] | ["?"] => Ok(Command::Help),
+            ["quit"] | ["q"] => Ok(Command::Quit),
+            _ => Err(format!("don't know how to `{}`, try `help`", s.trim())),
+        }
+    }
+}
+
+const DEBUGGER_HELP: &str = "\
+break N, b N       stop before running instruction N
+delete N, d N      forget the breakpoint at N
+watch acc [V]      stop when acc changes (or becomes V)
+unwatch            forget all the watches
+step [N], s [N]    run N instructions (1 if left off, or on an empty line)
+continue, c        run until something stops us
+regs, r            show ip, acc, lr and any other registers in use
+counts             show how many times each instruction has run
+output, o          show everything `out` has written
+list, l            show the program around ip
+reset              start over from the top
+quit, q            leave";
+
+/// Why the cpu isn't running any more
+#[derive(Debug, Eq, PartialEq)]
+enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Watch { old: isize, new: isize },
+    Finished,
+    Failed(CPUError),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Stepped => write!(f, "stepped"),
+            Stop::Breakpoint(ip) => write!(f, "breakpoint at {}", ip),
+            Stop::Watch { old, new } => write!(f, "acc changed from {} to {}", old, new),
+            Stop::Finished => write!(f, "program finished"),
+            Stop::Failed(e) => write!(f, "cpu stopped: {}", e),
+        }
+    }
+}
+
+/// Drives a CPU one command at a time, see DEBUGGER_HELP for what it understands
+struct Debugger {
+    cpu: CPU,
+    breakpoints: BTreeSet<usize>,
+    watches: Vec<Watch>,
+}
+
+impl Debugger {
+    fn new(cpu: CPU) -> Self {
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+        }
+    }
+
+    /// Runs one instruction, and says whether anything we're watching for happened
+    fn step_once(&mut self) -> Option<Stop> {
+        let old = self.cpu.acc();
+        match self.cpu.step() {
+            Ok(true) => {}
+            Ok(false) => return Some(Stop::Finished),
+            Err(e) => return Some(Stop::Failed(e)),
+        }
+        let new = self.cpu.acc();
+        let watched = self.watches.iter().any(|w| match w {
+            Watch::Changes => old != new,
+            Watch::Equals(v) => old != new && new == *v,
+        });
+        if watched {
+            Some(Stop::Watch { old, new })
+        } else {
+            None
+        }
+    }
+
+    /// Runs `limit` instructions, or forever if there isn't one. Breakpoints only
+    /// count once we've moved, so continuing from one doesn't stop straight away.
+    fn run_for(&mut self, limit: Option<usize>) -> Stop {
+        let mut ran = 0;
+        loop {
+            if ran > 0 && self.breakpoints.contains(&self.cpu.ip) {
+                return Stop::Breakpoint(self.cpu.ip);
+            }
+            if limit == Some(ran) {
+                return Stop::Stepped;
+            }
+            if let Some(stop) = self.step_once() {
+                return stop;
+            }
+            ran += 1;
+        }
+    }
+
+    fn write_registers(&self, out: &mut impl Write) -> io::Result<()> {
+        let at = match self.cpu.code.get(self.cpu.ip) {
+            Some(ins) => ins.to_string(),
+            None => "end of program".to_string(),
+        };
+        write!(
+            out,
+            "ip {} ({})  acc {}  lr {}",
+            self.cpu.ip,
+            at,
+            self.cpu.acc(),
+            self.cpu.regs.lr
+        )?;
+        // the other registers only get in the way if nothing's using them
+        for (n, value) in self.cpu.regs.r.iter().enumerate().skip(1) {
+            if *value != 0 {
+                write!(out, "  r{} {}", n, value)?;
+            }
+        }
+        writeln!(out)
+    }
+
+    /// Only the instructions that have run, with a bar to make hot spots obvious
+    fn write_counts(&self, out: &mut impl Write) -> io::Result<()> {
+        let most = self.cpu.counts.iter().copied().max().unwrap_or(0);
+        if most == 0 {
+            return writeln!(out, "nothing has run yet");
+        }
+        for (ip, count) in self.cpu.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let bar = "#".repeat((count * 40 + most - 1) / most);
+            writeln!(
+                out,
+                "{:>5}  {:<8} {:>6}  {}",
+                ip,
+                self.cpu.code[ip].to_string(),
+                count,
+                bar
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_listing(&self, out: &mut impl Write) -> io::Result<()> {
+        let from = self.cpu.ip.saturating_sub(3);
+        let to = (self.cpu.ip + 4).min(self.cpu.code.len());
+        for ip in from..to {
+            let marker = match (ip == self.cpu.ip, self.breakpoints.contains(&ip)) {
+                (true, true) => "*>",
+                (true, false) => " >",
+                (false, true) => "* ",
+                (false, false) => "  ",
+            };
+            writeln!(out, "{} {:>5}  {}", marker, ip, self.cpu.code[ip])?;
+        }
+        Ok(())
+    }
+
+    /// Carries out a command, false means it's time to go
+    fn execute(&mut self, command: Command, out: &mut impl Write) -> io::Result<bool> {
+        match command {
+            Command::Break(ip) if ip >= self.cpu.code.len() => {
+                writeln!(out, "there are only {} instructions", self.cpu.code.len())?
+            }
+            Command::Break(ip) => {
+                self.breakpoints.insert(ip);
+                writeln!(out, "breakpoint at {}", ip)?
+            }
+            Command::Delete(ip) => {
+                if !self.breakpoints.remove(&ip) {
+                    writeln!(out, "there wasn't a breakpoint at {}", ip)?
+                }
+            }
+            Command::Watch(watch) => self.watches.push(watch),
+            Command::Unwatch => self.watches.clear(),
+            Command::Step(n) => {
+                let stop = self.run_for(Some(n));
+                if stop != Stop::Stepped {
+                    writeln!(out, "{}", stop)?;
+                }
+                self.write_registers(out)?
+            }
+            Command::Continue => {
+                let stop = self.run_for(None);
+                writeln!(out, "{}", stop)?;
+                self.write_registers(out)?
+            }
+            Command::Registers => self.write_registers(out)?,
+            Command::Counts => self.write_counts(out)?,
+            Command::Output => {
+                let output: Vec<String> = self
+                    .cpu
+                    .regs
+                    .output
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                writeln!(out, "{}", output.join(" "))?
+            }
+            Command::List => self.write_listing(out)?,
+            Command::Reset => {
+                self.cpu.reset();
+                self.write_registers(out)?
+            }
+            Command::Help => writeln!(out, "{}", DEBUGGER_HELP)?,
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Reads commands from `input` until it runs out or someone says quit
+fn debug(cpu: CPU, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+    let mut debugger = Debugger::new(cpu);
+    debugger.write_registers(&mut out)?;
+    for line in input.lines() {
+        match line?.parse() {
+            Ok(command) => {
+                if !debugger.execute(command, &mut out)? {
+                    break;
+                }
+            }
+            Err(e) => writeln!(out, "{}", e)?,
+        }
+    }
+    Ok(())
+}
+
+/// One instruction swapped for another
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Patch {
+    ip: usize,
+    was: Instruction,
+    now: Instruction,
+}
+
+/// A patch that gets the program to the end, and what acc was when it got there
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Repair {
+    patch: Patch,
+    acc: isize,
+}
+
+/// Opcodes that can stand in for each other, the argument stays as it is
+type Substitutions = Vec<(Opcode, Opcode)>;
+
+/// The puzzle's fix: a `jmp` that should be a `nop` or the other way round
+fn jmp_nop_swaps() -> Substitutions {
+    vec![(Opcode::JMP, Opcode::NOP), (Opcode::NOP, Opcode::JMP)]
+}
+
+/// `jmp=nop,nop=jmp` and so on
+fn parse_substitutions(s: &str) -> Result<Substitutions, ParseError> {
+    s.split(',')
+        .map(|pair| match pair.split_once('=') {
+            Some((from, to)) => Ok((from.trim().parse()?, to.trim().parse()?)),
+            None => Err(ParseError::Malformed(pair.to_string())),
+        })
+        .collect()
+}
+
+/// Runs the cpu until it stops, giving back acc if it made it to the end
+fn run_to_end(cpu: &mut CPU) -> Option<isize> {
+    cpu.run().ok().map(|_| cpu.acc())
+}
+
+/// Every single-instruction patch allowed by `substitutions` that gets `program`
+/// to finish, in program order. One cpu gets patched, run and put back for each try.
+fn repairs(
+    program: &[Instruction],
+    substitutions: &[(Opcode, Opcode)],
+    policy: LoopPolicy,
+) -> Vec<Repair> {
+    let mut cpu = CPU::with_program(program.to_vec()).with_policy(policy);
+    let mut found = Vec::new();
+    for (ip, &was) in program.iter().enumerate() {
+        for &(from, to) in substitutions {
+            if was.op != from || from == to {
+                continue;
+            }
+            let now = Instruction { op: to, ..was };
+            cpu.code[ip] = now;
+            cpu.reset();
+            if let Some(acc) = run_to_end(&mut cpu) {
+                found.push(Repair {
+                    patch: Patch { ip, was, now },
+                    acc,
+                });
+            }
+        }
+        cpu.code[ip] = was;
+    }
+    found
+}
+
+/// Where the instruction at `ip` goes next: somewhere in the program, the end
+/// (`len`), or off either side (`None`). Anything that depends on the registers
+/// goes nowhere as far as this is concerned.
+fn successor(ip: usize, ins: Instruction, len: usize) -> Option<usize> {
+    let next = match ins.op.flow() {
+        Flow::FallThrough => ip as isize + 1,
+        Flow::Jump => (ip as isize).checked_add(ins.arg)?,
+        Flow::Branch | Flow::Dynamic => return None,
     };
+    if next >= 0 && next as usize <= len {
+        Some(next as usize)
+    } else {
+        None
+    }
+}
 
-    loop {
-        let r = cpu.step();
-        match r {
-            Ok(true) => {}, // keep running
-            Ok(false) => return Some(cpu.acc),
-            Err(CPUError::IndexOutOfBounds(612)) => return Some(cpu.acc),
-            Err(_) => return None, // error, no valid answer here
+/// Control flow of a program, with the end of the program as an extra node at `len`
+#[derive(Debug)]
+struct ControlFlow {
+    next: Vec<Option<usize>>,
+    /// whether starting here gets you to the end, worked out backwards from the end
+    finishes: Vec<bool>,
+}
+
+impl ControlFlow {
+    fn new(program: &[Instruction]) -> Self {
+        let len = program.len();
+        let next: Vec<Option<usize>> = program
+            .iter()
+            .enumerate()
+            .map(|(ip, ins)| successor(ip, *ins, len))
+            .collect();
+        let mut came_from = vec![Vec::new(); len + 1];
+        for (ip, next) in next.iter().enumerate() {
+            if let Some(next) = next {
+                came_from[*next].push(ip);
+            }
+        }
+        let mut finishes = vec![false; len + 1];
+        finishes[len] = true;
+        let mut todo = vec![len];
+        while let Some(ip) = todo.pop() {
+            for &from in &came_from[ip] {
+                if !finishes[from] {
+                    finishes[from] = true;
+                    todo.push(from);
+                }
+            }
+        }
+        ControlFlow { next, finishes }
+    }
+}
+
+/// `jmp` for `nop` and the other way round
+fn flip(ins: Instruction) -> Option<Instruction> {
+    let op = if ins.op == Opcode::JMP {
+        Opcode::NOP
+    } else if ins.op == Opcode::NOP {
+        Opcode::JMP
+    } else {
+        return None;
+    };
+    Some(Instruction { op, ..ins })
+}
+
+/// Which single jmp/nop flips fix a broken program, without running anything.
+/// Only what the program actually runs can matter, and a flip there fixes things
+/// if where it goes instead already leads to the end. It can't lead back through the
+/// flip, or the unflipped program would have finished. For the same reason this
+/// only works on a program that doesn't already finish: that gets `None`. So does
+/// anything with branches or `ret`s in, since where they go isn't fixed.
+fn flips_that_finish(program: &[Instruction]) -> Option<Vec<usize>> {
+    let fixed = |ins: &Instruction| matches!(ins.op.flow(), Flow::FallThrough | Flow::Jump);
+    if !program.iter().all(fixed) {
+        return None;
+    }
+    let len = program.len();
+    let flow = ControlFlow::new(program);
+    let mut seen = vec![false; len];
+    // kept as flags so they come out in program order, like `repairs` gives them
+    let mut fixes = vec![false; len];
+    let mut ip = 0;
+    while ip < len && !seen[ip] {
+        seen[ip] = true;
+        let ins = program[ip];
+        let instead = flip(ins).and_then(|flipped| successor(ip, flipped, len));
+        fixes[ip] = instead.map_or(false, |to| flow.finishes[to]);
+        match flow.next[ip] {
+            Some(next) => ip = next,
+            None => break,
+        }
+    }
+    if ip == len {
+        return None;
+    }
+    Some((0..len).filter(|ip| fixes[*ip]).collect())
+}
+
+/// The jmp/nop `repairs`, but only running the fixes `flips_that_finish` finds.
+/// `None` when that can't say, and `repairs` has to try them all.
+fn quick_repairs(program: &[Instruction]) -> Option<Vec<Repair>> {
+    let mut cpu = CPU::with_program(program.to_vec());
+    let found = flips_that_finish(program)?
+        .into_iter()
+        .filter_map(|ip| {
+            let was = program[ip];
+            let now = flip(was)?;
+            cpu.code[ip] = now;
+            cpu.reset();
+            let acc = run_to_end(&mut cpu);
+            cpu.code[ip] = was;
+            Some(Repair {
+                patch: Patch { ip, was, now },
+                acc: acc?,
+            })
+        })
+        .collect();
+    Some(found)
+}
+
+/// `day8 run [policy]` runs the program as-is, printing what it `out`s and then acc
+fn run(data: &str, policy: LoopPolicy) {
+    let mut cpu = match CPU::new(data) {
+        Ok(cpu) => cpu.with_policy(policy),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let result = cpu.run();
+    for value in &cpu.regs.output {
+        println!("{}", value);
+    }
+    match result {
+        Ok(()) => println!("acc: {}", cpu.acc()),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     }
 }
 
+/// `day8 trace [text|json] [policy]` runs the program as-is, printing everything it does
+/// to stdout and, if it gets stuck, which instructions it's going round in to stderr
+fn trace(data: &str, format: TraceFormat, policy: LoopPolicy) {
+    let mut cpu = match CPU::new(data) {
+        Ok(cpu) => cpu.with_policy(policy),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    cpu.record();
+    let result = cpu.run();
+    if let Some(trace) = &cpu.trace {
+        trace.write(format, &mut io::stdout().lock()).unwrap();
+    }
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        if let Some(cycle) = cpu.loop_cycle() {
+            let cycle: Vec<String> = cycle.iter().map(ToString::to_string).collect();
+            eprintln!("repeating: {}", cycle.join(" -> "));
+        }
+        std::process::exit(1);
+    }
+}
+
 fn main() {
+    // `day8 debug <program>` takes its commands from stdin, so the program has to come from a file
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("debug") {
+        let cpu = args
+            .get(2)
+            .ok_or_else(|| "usage: day8 debug <program> [policy]".to_string())
+            .and_then(|path| std::fs::read_to_string(path).map_err(|e| e.to_string()))
+            .and_then(|code| CPU::new(&code).map_err(|e| e.to_string()))
+            .and_then(|cpu| {
+                let policy = args.get(3).map_or("revisit", String::as_str).parse()?;
+                Ok(cpu.with_policy(policy))
+            });
+        match cpu {
+            Ok(cpu) => debug(cpu, stdin().lock(), io::stdout()).unwrap(),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let mut r = BufReader::new(stdin());
     let mut data = String::new();
     r.read_to_string(&mut data).unwrap();
 
-    let ix_count = data.lines().count();
-    for i in 0..ix_count {
-        let mut cpu = CPU::new(&data);
-        if let Some(i) = run_with_swapped(i, &mut cpu) {
-            println!("cpu stopped, acc: {}", i);
+    // `day8 asm` and `day8 disasm` translate stdin rather than running it
+    match args.get(1).map(String::as_str) {
+        Some("asm") => match assemble(&data) {
+            Ok(program) => {
+                for ins in program {
+                    println!("{}", ins);
+                }
+                return;
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        Some("run") => {
+            match args.get(2).map_or("revisit", String::as_str).parse() {
+                Ok(policy) => run(&data, policy),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
         }
+        Some("trace") => {
+            let format = args.get(2).map_or("text", String::as_str).parse();
+            let policy = args.get(3).map_or("revisit", String::as_str).parse();
+            match (format, policy) {
+                (Ok(format), Ok(policy)) => trace(&data, format, policy),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some("disasm") => match parse_program(&data) {
+            Ok(program) => {
+                print!("{}", disassemble(&program));
+                return;
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => {}
+    }
+
+    // otherwise look for fixes, `day8 --swap acc=nop` to try something other than jmp/nop
+    let substitutions = match args.get(1).map(String::as_str) {
+        Some("--swap") => args
+            .get(2)
+            .ok_or(ParseError::Malformed(String::new()))
+            .and_then(|s| parse_substitutions(s))
+            .map(Some),
+        _ => Ok(None),
+    };
+    let program = parse_program(&data).map_err(|e| e.to_string());
+    let (program, substitutions) = match (program, substitutions) {
+        (Ok(program), Ok(substitutions)) => (program, substitutions),
+        (Err(e), _) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        (_, Err(e)) => {
+            eprintln!("--swap: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let found = match substitutions {
+        Some(substitutions) => repairs(&program, &substitutions, LoopPolicy::default()),
+        // plain jmp/nop swaps can be worked out without trying every one
+        None => quick_repairs(&program)
+            .unwrap_or_else(|| repairs(&program, &jmp_nop_swaps(), LoopPolicy::default())),
+    };
+    for Repair { patch, acc } in found {
+        println!(
+            "cpu stopped with {} changed from `{}` to `{}`, acc: {}",
+            patch.ip, patch.was, patch.now, acc
+        );
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::xorshift::XorShift;
     use super::*;
     const DATA: &str = "nop +0
                         acc +1
@@ -156,13 +1431,13 @@ mod tests {
 
     #[test]
     fn run_program() {
-        let mut cpu = CPU::new(DATA);
+        let mut cpu = CPU::new(DATA).unwrap();
         cpu.step().unwrap();
         assert_eq!(cpu.ip, 1);
-        assert_eq!(cpu.acc, 0);
+        assert_eq!(cpu.acc(), 0);
         cpu.step().unwrap();
         assert_eq!(cpu.ip, 2);
-        assert_eq!(cpu.acc, 1);
+        assert_eq!(cpu.acc(), 1);
         cpu.step().unwrap();
         assert_eq!(cpu.ip, 6);
     }
@@ -178,7 +1453,7 @@ mod tests {
                          acc +1
                          nop -4
                          acc +6";
-        let mut cpu = CPU::new(data);
+        let mut cpu = CPU::new(data).unwrap();
         loop {
             let r = cpu.step();
             match r {
@@ -191,24 +1466,602 @@ mod tests {
 
     #[test]
     fn runs_with_swapped() {
-        let mut cpu = CPU::new("nop +0\nacc +5\njmp +2");
-        run_with_swapped(0, &mut cpu);
-        run_with_swapped(1, &mut cpu);
-        run_with_swapped(2, &mut cpu);
-        assert_eq!(cpu.code, vec!["jmp +0", "acc +5", "nop +2"]);
+        let program = parse_program("nop +0\nacc +5\njmp -2").unwrap();
+        let found = repairs(&program, &jmp_nop_swaps(), LoopPolicy::default());
+        assert_eq!(
+            found,
+            vec![Repair {
+                patch: Patch {
+                    ip: 2,
+                    was: "jmp -2".parse().unwrap(),
+                    now: "nop -2".parse().unwrap()
+                },
+                acc: 5
+            }]
+        );
+    }
+
+    #[test]
+    fn repairs_puzzle() {
+        let program = parse_program(DATA).unwrap();
+        let found = repairs(&program, &jmp_nop_swaps(), LoopPolicy::default());
+        let fixes: Vec<(usize, isize)> = found.iter().map(|r| (r.patch.ip, r.acc)).collect();
+        assert_eq!(fixes, vec![(7, 8)]);
+        // a jump to just past the end is a clean finish
+        let program = parse_program("acc +1\nnop +2\njmp -2").unwrap();
+        let found = repairs(&program, &jmp_nop_swaps(), LoopPolicy::default());
+        let fixes: Vec<(usize, isize)> = found.iter().map(|r| (r.patch.ip, r.acc)).collect();
+        assert_eq!(fixes, vec![(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn repairs_with_other_substitutions() {
+        let program = parse_program("nop +0\nacc +2\njmp -1").unwrap();
+        let swaps = parse_substitutions("acc=jmp").unwrap();
+        let found = repairs(&program, &swaps, LoopPolicy::default());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].patch.now.to_string(), "jmp +2");
+        assert_eq!(found[0].acc, 0);
+        assert_eq!(
+            parse_substitutions("acc=jmp,nop"),
+            Err(ParseError::Malformed("nop".to_string()))
+        );
+        assert_eq!(
+            parse_substitutions("acc=mul"),
+            Err(ParseError::UnknownInstruction("mul".to_string()))
+        );
     }
 
     #[test]
     fn test_loop() {
-        let mut cpu = CPU::new("nop +0\njmp -1");
+        let mut cpu = CPU::new("nop +0\njmp -1").unwrap();
+        assert_eq!(cpu.step(), Ok(true));
         assert_eq!(cpu.step(), Ok(true));
         assert_eq!(cpu.step(), Err(CPUError::WouldLoop { ip: 1, dst: 0 }));
     }
 
     #[test]
     fn test_jumped_out_of_bounds() {
-        let mut cpu = CPU::new("nop +0\njmp +2\nnop -99");
+        let mut cpu = CPU::new("nop +0\njmp +3\nnop -99").unwrap();
+        assert_eq!(cpu.step(), Ok(true));
+        assert_eq!(cpu.step(), Err(CPUError::JumpPastEnd { ip: 1, dst: 4 }));
+        // nothing moved
+        assert_eq!((cpu.ip, cpu.counts[1]), (1, 0));
+
+        let mut cpu = CPU::new("nop +0\njmp -2\nnop -99").unwrap();
         assert_eq!(cpu.step(), Ok(true));
-        assert_eq!(cpu.step(), Err(CPUError::IndexOutOfBounds(3)));
+        assert_eq!(
+            cpu.step(),
+            Err(CPUError::JumpBeforeStart { ip: 1, dst: -1 })
+        );
+
+        let mut cpu = CPU::new("jmp -9223372036854775807").unwrap();
+        assert_eq!(
+            cpu.step(),
+            Err(CPUError::JumpBeforeStart {
+                ip: 0,
+                dst: -9223372036854775807
+            })
+        );
+        let mut cpu = CPU::new("nop +0\njmp +9223372036854775807").unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.step(),
+            Err(CPUError::JumpPastEnd {
+                ip: 1,
+                dst: isize::MAX as usize
+            })
+        );
+    }
+
+    #[test]
+    fn jumping_to_the_end_finishes() {
+        let mut cpu = CPU::new("nop +0\njmp +2\nnop -99").unwrap();
+        assert_eq!(cpu.step(), Ok(true));
+        assert_eq!(cpu.step(), Ok(true));
+        assert_eq!(cpu.ip, 3);
+        assert_eq!(cpu.step(), Ok(false));
+        // falling off works the same
+        let mut cpu = CPU::new("acc +1\nacc +1").unwrap();
+        assert_eq!(cpu.run(), Ok(()));
+        assert_eq!((cpu.ip, cpu.acc()), (2, 2));
+        // and so does jumping straight there from the top, or to the first instruction
+        assert_eq!(CPU::new("jmp +1").unwrap().run(), Ok(()));
+        assert_eq!(
+            CPU::new("acc +1\njmp -1").unwrap().run(),
+            Err(CPUError::WouldLoop { ip: 1, dst: 0 })
+        );
+        let mut cpu = CPU::new("jmp +2\nacc +1").unwrap();
+        assert_eq!(cpu.run(), Ok(()));
+        assert_eq!(cpu.acc(), 0);
+    }
+
+    #[test]
+    fn test_parse_instruction() {
+        let ins: Instruction = "jmp -4".parse().unwrap();
+        assert_eq!(
+            ins,
+            Instruction {
+                op: Opcode::JMP,
+                reg: 0,
+                arg: -4
+            }
+        );
+        assert_eq!(ins.to_string(), "jmp -4");
+        assert_eq!(
+            "  acc +17 ".parse::<Instruction>().unwrap().to_string(),
+            "acc +17"
+        );
+        assert_eq!(
+            "mul +2".parse::<Instruction>(),
+            Err(ParseError::UnknownInstruction("mul".to_string()))
+        );
+        assert_eq!(
+            "nop two".parse::<Instruction>(),
+            Err(ParseError::IntegerDecode("two".to_string()))
+        );
+        assert_eq!(
+            "nop".parse::<Instruction>(),
+            Err(ParseError::Malformed("nop".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_errors_have_line_numbers() {
+        assert_eq!(
+            parse_program("nop +0\n\nacc +1\njump +3\n").unwrap_err(),
+            CPUError::Parse {
+                line: 4,
+                source: ParseError::UnknownInstruction("jump".to_string())
+            }
+        );
+        assert_eq!(parse_program("nop +0\n\nacc +1\n").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_assemble() {
+        let source = "# count to three, then leave
+                      start:
+                          acc +1      ; bump it
+                      loop: jmp skip
+                          acc +100
+                      skip:
+                          nop loop    # flip me
+                          jmp end
+                      end:";
+        let program = assemble(source).unwrap();
+        let text: Vec<String> = program.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            text,
+            vec!["acc +1", "jmp +2", "acc +100", "nop -2", "jmp +1"]
+        );
+        assert!(assemble("a: b: nop a").is_ok());
+    }
+
+    #[test]
+    fn assembler_errors() {
+        assert_eq!(
+            assemble("nop +0\njmp nowhere"),
+            Err(AsmError::UnknownLabel {
+                line: 2,
+                label: "nowhere".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("here:\nnop +0\nhere: jmp here"),
+            Err(AsmError::DuplicateLabel {
+                line: 3,
+                label: "here".to_string(),
+                first_line: 1
+            })
+        );
+        assert_eq!(
+            assemble("nop +0\nacc 1.5"),
+            Err(AsmError::Parse {
+                line: 2,
+                source: ParseError::IntegerDecode("1.5".to_string())
+            })
+        );
+        assert_eq!(
+            assemble("hop +1"),
+            Err(AsmError::Parse {
+                line: 1,
+                source: ParseError::UnknownInstruction("hop".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let program = parse_program("nop +0\nacc +1\njmp +2\njmp -3\njmp +2\njmp -9").unwrap();
+        assert_eq!(
+            disassemble(&program),
+            "block0:
+    nop +0          ; 0 -> 0 as a jmp
+    acc +1          ; 1
+    jmp block2      ; 2 -> 4
+block1:
+    jmp block0      ; 3 -> 0
+block2:
+    jmp end         ; 4 -> 6
+block3:
+    jmp -9          ; 5 -> outside the program
+end:
+"
+        );
+    }
+
+    #[test]
+    fn disassembling_huge_arguments() {
+        let program = parse_program(
+            "nop +9223372036854775807\nacc +9223372036854775807\njmp +9223372036854775807",
+        )
+        .unwrap();
+        assert_eq!(
+            disassemble(&program),
+            "block0:
+    nop +9223372036854775807; 0 -> outside as a jmp
+    acc +9223372036854775807; 1
+    jmp +9223372036854775807; 2 -> outside the program
+"
+        );
+        assert_eq!(assemble(&disassemble(&program)), Ok(program));
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        for source in [DATA, include_str!("../../inputs/day8.txt")].iter() {
+            let program = parse_program(source).unwrap();
+            assert_eq!(assemble(&disassemble(&program)), Ok(program));
+        }
+    }
+
+    fn debug_session(script: &str) -> String {
+        let mut out = Vec::new();
+        debug(CPU::new(DATA).unwrap(), script.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn debugger_commands_parse() {
+        assert_eq!("b 4".parse(), Ok(Command::Break(4)));
+        assert_eq!("".parse(), Ok(Command::Step(1)));
+        assert_eq!("step 3".parse(), Ok(Command::Step(3)));
+        assert_eq!(
+            "watch acc -2".parse(),
+            Ok(Command::Watch(Watch::Equals(-2)))
+        );
+        assert!("break here".parse::<Command>().is_err());
+        assert!("fly".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn debugger_breakpoints_and_steps() {
+        let out = debug_session("b 6\nc\ns\nr\nd 6\nc\nq\nr");
+        assert_eq!(
+            out,
+            "ip 0 (nop +0)  acc 0  lr 0
+breakpoint at 6
+breakpoint at 6
+ip 6 (acc +1)  acc 1  lr 0
+ip 7 (jmp -4)  acc 2  lr 0
+ip 7 (jmp -4)  acc 2  lr 0
+cpu stopped: loop detected at 4 to 1
+ip 1 (acc +1)  acc 5  lr 0
+"
+        );
+    }
+
+    #[test]
+    fn debugger_watches_acc() {
+        let out = debug_session("watch acc 5\nc\nunwatch\nwatch acc\nc\nreset\ncounts");
+        assert_eq!(
+            out,
+            "ip 0 (nop +0)  acc 0  lr 0
+acc changed from 2 to 5
+ip 4 (jmp -3)  acc 5  lr 0
+cpu stopped: loop detected at 4 to 1
+ip 1 (acc +1)  acc 5  lr 0
+ip 0 (nop +0)  acc 0  lr 0
+nothing has run yet
+"
+        );
+    }
+
+    #[test]
+    fn debugger_counts() {
+        let out = debug_session("s 3\ncounts");
+        assert!(out.ends_with(
+            "    0  nop +0        1  ########################################
+    1  acc +1        1  ########################################
+    2  jmp +4        1  ########################################
+"
+        ));
+    }
+
+    #[test]
+    fn trace_records_acc() {
+        let mut cpu = CPU::new(DATA).unwrap();
+        cpu.record();
+        assert!(matches!(cpu.run(), Err(CPUError::WouldLoop { .. })));
+        let trace = cpu.trace.as_ref().unwrap();
+        let ips: Vec<usize> = trace.entries.iter().map(|e| e.ip).collect();
+        assert_eq!(ips, vec![0, 1, 2, 6, 7, 3, 4]);
+        assert_eq!(
+            trace.entries[1],
+            TraceEntry {
+                ip: 1,
+                ins: Instruction {
+                    op: Opcode::ACC,
+                    reg: 0,
+                    arg: 1
+                },
+                acc_before: 0,
+                acc_after: 1
+            }
+        );
+        assert_eq!(cpu.loop_cycle(), Some(vec![1, 2, 6, 7, 3, 4]));
+    }
+
+    #[test]
+    fn trace_exports() {
+        let mut cpu = CPU::new("nop +0\nacc -2").unwrap();
+        cpu.record();
+        cpu.run().unwrap();
+        let trace = cpu.trace.unwrap();
+        let mut text = Vec::new();
+        trace.write(TraceFormat::Text, &mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "    0  nop +0   acc 0 -> 0\n    1  acc -2   acc 0 -> -2\n"
+        );
+        let mut json = Vec::new();
+        trace.write(TraceFormat::JsonLines, &mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            r#"{"ip":0,"op":"nop","reg":0,"arg":0,"acc_before":0,"acc_after":0}
+{"ip":1,"op":"acc","reg":0,"arg":-2,"acc_before":0,"acc_after":-2}
+"#
+        );
+    }
+
+    #[test]
+    fn no_trace_unless_asked() {
+        let mut cpu = CPU::new(DATA).unwrap();
+        assert!(cpu.run().is_err());
+        assert!(cpu.trace.is_none());
+        assert_eq!(cpu.loop_cycle(), None);
+    }
+
+    #[test]
+    fn loops_through_fall_through() {
+        // 5 jumps back to 3, which hasn't run yet, and then falls into 4, which has
+        let mut cpu = CPU::new("nop +0\nacc +1\njmp +2\nacc +5\nacc +3\njmp -2").unwrap();
+        assert_eq!(cpu.run(), Err(CPUError::WouldLoop { ip: 3, dst: 4 }));
+        assert_eq!(cpu.acc(), 9);
+        assert_eq!(cpu.counts, vec![1, 1, 1, 1, 1, 1]);
+        // failing didn't change anything, so it fails the same way again
+        assert_eq!(cpu.step(), Err(CPUError::WouldLoop { ip: 3, dst: 4 }));
+        assert_eq!(cpu.acc(), 9);
+    }
+
+    #[test]
+    fn loops_to_itself() {
+        let mut cpu = CPU::new("jmp +0").unwrap();
+        assert_eq!(cpu.step(), Ok(true));
+        assert_eq!(cpu.step(), Err(CPUError::WouldLoop { ip: 0, dst: 0 }));
+        let mut cpu = CPU::new("jmp +0")
+            .unwrap()
+            .with_policy(LoopPolicy::MaxVisits(3));
+        assert_eq!(cpu.run(), Err(CPUError::WouldLoop { ip: 0, dst: 0 }));
+        assert_eq!(cpu.counts, vec![3]);
+    }
+
+    #[test]
+    fn loop_policy_max_visits() {
+        let mut cpu = CPU::new("acc +1\njmp -1")
+            .unwrap()
+            .with_policy(LoopPolicy::MaxVisits(3));
+        assert_eq!(cpu.run(), Err(CPUError::WouldLoop { ip: 1, dst: 0 }));
+        assert_eq!(cpu.acc(), 3);
+        assert_eq!(cpu.counts, vec![3, 3]);
+    }
+
+    #[test]
+    fn overflow_stops_the_cpu() {
+        let mut cpu = CPU::new("acc +9223372036854775807\nacc +1").unwrap();
+        assert_eq!(cpu.run(), Err(CPUError::Overflow { ip: 1 }));
+        assert_eq!(cpu.acc(), isize::MAX);
+        assert_eq!(cpu.counts, vec![1, 0]);
+        let mut cpu = CPU::new("acc -9223372036854775807\nout -2").unwrap();
+        assert_eq!(cpu.run(), Err(CPUError::Overflow { ip: 1 }));
+        assert!(cpu.regs.output.is_empty());
+    }
+
+    #[test]
+    fn loop_policy_never() {
+        let policy = LoopPolicy::Never { budget: 9 };
+        let mut cpu = CPU::new("acc +1\njmp -1").unwrap().with_policy(policy);
+        assert_eq!(cpu.run(), Err(CPUError::OutOfSteps { budget: 9 }));
+        assert_eq!(cpu.acc(), 5);
+        assert_eq!(cpu.steps, 9);
+        // a program that finishes inside the budget is fine
+        let mut cpu = CPU::new("acc +1\nacc +1").unwrap().with_policy(policy);
+        assert_eq!(cpu.run(), Ok(()));
+        assert_eq!(cpu.acc(), 2);
+    }
+
+    #[test]
+    fn parse_loop_policy() {
+        assert_eq!("revisit".parse(), Ok(LoopPolicy::AnyRevisit));
+        assert_eq!("visits=3".parse(), Ok(LoopPolicy::MaxVisits(3)));
+        assert_eq!("budget=100".parse(), Ok(LoopPolicy::Never { budget: 100 }));
+        assert!("visits=lots".parse::<LoopPolicy>().is_err());
+        assert!("visits=0".parse::<LoopPolicy>().is_err());
+        assert!("forever".parse::<LoopPolicy>().is_err());
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut cpu = CPU::new(DATA).unwrap();
+        cpu.record();
+        assert_eq!(cpu.run(), Err(CPUError::WouldLoop { ip: 4, dst: 1 }));
+        let first = (
+            cpu.acc(),
+            cpu.counts.clone(),
+            cpu.trace.as_ref().unwrap().entries.clone(),
+        );
+
+        cpu.reset();
+        assert_eq!((cpu.ip, cpu.acc(), cpu.regs.lr, cpu.steps), (0, 0, 0, 0));
+        assert!(cpu.counts.iter().all(|c| *c == 0));
+        assert!(cpu.trace.as_ref().unwrap().entries.is_empty());
+
+        assert_eq!(cpu.run(), Err(CPUError::WouldLoop { ip: 4, dst: 1 }));
+        let second = (
+            cpu.acc(),
+            cpu.counts.clone(),
+            cpu.trace.as_ref().unwrap().entries.clone(),
+        );
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn control_flow_finishes() {
+        let program = parse_program(DATA).unwrap();
+        let flow = ControlFlow::new(&program);
+        let finishing: Vec<usize> = (0..=program.len())
+            .filter(|ip| flow.finishes[*ip])
+            .collect();
+        assert_eq!(finishing, vec![8, 9]);
+        assert_eq!(flips_that_finish(&program), Some(vec![7]));
+        // nothing to fix
+        assert_eq!(
+            flips_that_finish(&parse_program("nop +0\nacc +1").unwrap()),
+            None
+        );
+        assert_eq!(flips_that_finish(&parse_program("jmp +1").unwrap()), None);
+    }
+
+    /// Random-ish programs, but the same every run
+    fn random_program(rng: &mut XorShift, len: usize) -> Vec<Instruction> {
+        (0..len)
+            .map(|_| {
+                let op = [Opcode::NOP, Opcode::ACC, Opcode::JMP][rng.below(3)];
+                let arg = rng.below(2 * len + 3) as isize - len as isize - 1;
+                Instruction { op, reg: 0, arg }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quick_repairs_match_brute_force() {
+        let mut rng = XorShift::new(0x2020_0808);
+        let mut checked = 0;
+        for len in 1..40 {
+            for _ in 0..50 {
+                let program = random_program(&mut rng, len);
+                let brute = repairs(&program, &jmp_nop_swaps(), LoopPolicy::default());
+                match quick_repairs(&program) {
+                    Some(quick) => {
+                        assert_eq!(quick, brute, "{:?}", program);
+                        checked += 1;
+                    }
+                    None => assert!(run_to_end(&mut CPU::with_program(program)).is_some()),
+                }
+            }
+        }
+        assert!(checked > 1000);
+        for source in [DATA, include_str!("../../inputs/day8.txt")].iter() {
+            let program = parse_program(source).unwrap();
+            let brute = repairs(&program, &jmp_nop_swaps(), LoopPolicy::default());
+            assert_eq!(quick_repairs(&program), Some(brute));
+        }
+    }
+
+    const SUBROUTINES: &str = "    jmp main
+                               double:
+                                   acc +21
+                                   ret +0
+                               main:
+                                   set r1 +3
+                               loop:
+                                   out r1 +0
+                                   acc r1 -1
+                                   jnz r1 loop
+                                   call double
+                                   call double
+                                   out +0
+                                   jz r1 +2
+                                   out +99
+                                   out +7";
+
+    #[test]
+    fn parse_registers() {
+        let ins: Instruction = "jnz r2 -3".parse().unwrap();
+        assert_eq!((ins.op, ins.reg, ins.arg), (Opcode::JNZ, 2, -3));
+        assert_eq!(ins.to_string(), "jnz r2 -3");
+        assert_eq!(
+            "out r0 +1".parse::<Instruction>().unwrap().to_string(),
+            "out +1"
+        );
+        assert_eq!(
+            "acc r8 +1".parse::<Instruction>(),
+            Err(ParseError::UnknownRegister("r8".to_string()))
+        );
+        assert_eq!(
+            "acc b +1".parse::<Instruction>(),
+            Err(ParseError::UnknownRegister("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn runs_subroutines_and_branches() {
+        let program = assemble(SUBROUTINES).unwrap();
+        let mut cpu = CPU::with_program(program).with_policy(LoopPolicy::Never { budget: 100 });
+        assert_eq!(cpu.run(), Ok(()));
+        assert_eq!(cpu.regs.output, vec![3, 2, 1, 42, 49]);
+        assert_eq!(cpu.acc(), 42);
+        assert_eq!(cpu.regs.r[1], 0);
+        assert_eq!(cpu.regs.lr, 9);
+        // the subroutine runs twice, which the puzzle's loop check won't have
+        let mut cpu = CPU::with_program(assemble(SUBROUTINES).unwrap());
+        assert!(matches!(cpu.run(), Err(CPUError::WouldLoop { .. })));
+    }
+
+    #[test]
+    fn analysis_leaves_branches_alone() {
+        let program = assemble(SUBROUTINES).unwrap();
+        assert_eq!(flips_that_finish(&program), None);
+        let text = disassemble(&program);
+        assert!(text.contains("jnz r1 block"), "{}", text);
+        assert_eq!(assemble(&text), Ok(program));
+    }
+
+    /// Doubles a register. Not in OPERATIONS, but the cpu can run it all the same.
+    struct Double;
+    impl Operation for Double {
+        fn name(&self) -> &'static str {
+            "dbl"
+        }
+
+        fn apply(&self, ip: usize, ins: Instruction, regs: &mut Registers) -> Result<(), CPUError> {
+            regs.r[ins.reg] = regs.r[ins.reg]
+                .checked_mul(2)
+                .ok_or(CPUError::Overflow { ip })?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn new_operations_need_no_cpu_changes() {
+        let mut program = parse_program("acc +3\nnop +0\nnop +0").unwrap();
+        program[1].op = Opcode(&Double);
+        program[2].op = Opcode(&Double);
+        let mut cpu = CPU::with_program(program);
+        assert_eq!(cpu.run(), Ok(()));
+        assert_eq!(cpu.acc(), 12);
+        assert_eq!(cpu.code[1].to_string(), "dbl +0");
+        assert_ne!(cpu.code[1].op, Opcode::NOP);
     }
 }
@@ -1,76 +1,375 @@
+use std::collections::{HashMap, VecDeque};
 use std::io::BufRead;
-use std::io::{stdin, BufReader, Read};
+use std::io::{stdin, BufReader};
+use std::ops::Range;
+use std::str::FromStr;
 use thiserror::Error;
+use xorshift::XorShift;
 
-/// Returns an Option containing the "partner" number
-/// if this is a valid XMAS number
-fn valid_xmas(window: &[u32]) -> Option<(u32, u32)> {
-    let needle = window.last().cloned()?;
-    let preamble = &window[0..window.len() - 1];
-    for i in 0..preamble.len() {
-        let partner = if let Some(x) = needle.checked_sub(preamble[i]) {
-            x
+#[path = "../xorshift.rs"]
+mod xorshift;
+
+#[derive(Error, Debug, Eq, PartialEq)]
+enum XmasError {
+    #[error("line {line}: `{text}` isn't a number")]
+    BadNumber { line: usize, text: String },
+    #[error("line {line}: couldn't read it: {reason}")]
+    Unreadable { line: usize, reason: String },
+}
+
+/// Hands out numbers as the lines come in. A line that isn't a number comes out as
+/// an error in its place, so it's up to the caller whether to carry on past it.
+fn read_numbers(r: impl BufRead) -> impl Iterator<Item = Result<u64, XmasError>> {
+    r.lines().enumerate().filter_map(|(i, line)| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(line.trim().parse().map_err(|_| XmasError::BadNumber {
+            line: i + 1,
+            text: line.trim().to_string(),
+        })),
+        Err(e) => Some(Err(XmasError::Unreadable {
+            line: i + 1,
+            reason: e.to_string(),
+        })),
+    })
+}
+
+#[derive(Error, Debug, Eq, PartialEq)]
+enum GenerateError {
+    #[error("a preamble of {0} doesn't have any pairs to add up")]
+    PreambleTooShort(usize),
+    #[error("{at} is still in the preamble of {preamble}, it can't be invalid")]
+    InvalidTooEarly { at: usize, preamble: usize },
+    #[error("{at} is past the end of a stream of {len}")]
+    InvalidTooLate { at: usize, len: usize },
+    /// invalid numbers right next to each other can use up the zeros that keep
+    /// the valid ones small
+    #[error("number {at} is too big for a u64, try spreading the invalid numbers out")]
+    Overflow { at: usize },
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Check {
+    /// still filling up the preamble, nothing to check against yet
+    Preamble,
+    Valid,
+    Invalid,
+}
+
+/// Checks numbers one at a time against the `preamble` before them. It keeps count
+/// of every sum a pair in the window makes, so a check is one lookup, and sliding
+/// the window along touches each pair with the number coming in or going out.
+struct Validator {
+    preamble: usize,
+    window: VecDeque<u64>,
+    /// how many pairs of numbers in the window add up to each sum
+    sums: HashMap<u64, usize>,
+}
+
+impl Validator {
+    fn new(preamble: usize) -> Self {
+        Validator {
+            preamble,
+            window: VecDeque::with_capacity(preamble + 1),
+            sums: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, n: u64) -> Check {
+        let check = if self.window.len() < self.preamble {
+            Check::Preamble
+        } else if self.sums.get(&n).map_or(false, |pairs| *pairs > 0) {
+            Check::Valid
         } else {
-            continue
+            Check::Invalid
         };
 
-        if let Some((_idx, x)) = preamble.iter().enumerate().find(|(j, &x)| {
-            if *j == i {
-                false // skip currently looked-at number
-            } else {
-                x == partner
+        if self.window.len() == self.preamble {
+            if let Some(old) = self.window.pop_front() {
+                for other in &self.window {
+                    // a sum too big for a u64 never got counted
+                    if let Some(sum) = old.checked_add(*other) {
+                        if let Some(pairs) = self.sums.get_mut(&sum) {
+                            *pairs -= 1;
+                            if *pairs == 0 {
+                                self.sums.remove(&sum);
+                            }
+                        }
+                    }
+                }
             }
-        }) {
-            return Some((needle - *x, *x));
         }
+        for other in &self.window {
+            if let Some(sum) = n.checked_add(*other) {
+                *self.sums.entry(sum).or_insert(0) += 1;
+            }
+        }
+        self.window.push_back(n);
+        check
+    }
+}
+
+/// Every number that isn't the sum of two of the `preamble` before it, with its
+/// index, as soon as it turns up
+fn invalid_numbers(
+    numbers: impl IntoIterator<Item = u64>,
+    preamble: usize,
+) -> impl Iterator<Item = (usize, u64)> {
+    let mut validator = Validator::new(preamble);
+    numbers
+        .into_iter()
+        .enumerate()
+        .filter(move |(_, n)| validator.push(*n) == Check::Invalid)
+}
+
+/// Every run of at least two numbers in a row that adds up to `needle`, in order of
+/// where they start. Sums only grow as a run gets longer, so for each start the ends
+/// that work sit together (more than one only if there are zeros), and both edges
+/// of that group only move forward as the start does.
+fn subsum_ranges(numbers: &[u64], needle: u64) -> Vec<Range<usize>> {
+    let n = numbers.len();
+    // prefix[i] is the sum of everything before i, u128 so long runs can't overflow
+    let mut prefix = Vec::with_capacity(n + 1);
+    prefix.push(0u128);
+    for x in numbers {
+        prefix.push(prefix[prefix.len() - 1] + *x as u128);
+    }
+
+    let mut found = Vec::new();
+    // first end that gets to the needle, and the first that goes past it
+    let (mut reach, mut past) = (0, 0);
+    for start in 0..n {
+        let want = prefix[start] + needle as u128;
+        reach = reach.max(start + 2);
+        while reach <= n && prefix[reach] < want {
+            reach += 1;
+        }
+        past = past.max(reach);
+        while past <= n && prefix[past] <= want {
+            past += 1;
+        }
+        found.extend((reach..past).map(|end| start..end));
+    }
+    found
+}
+
+/// The first run of at least two numbers that adds up to `needle`
+fn find_subsum(numbers: &[u64], needle: u64) -> Option<Range<usize>> {
+    subsum_ranges(numbers, needle).into_iter().next()
+}
+
+/// The puzzle wants the smallest and biggest numbers in the run added together
+fn weakness(run: &[u64]) -> Option<u128> {
+    Some(*run.iter().min()? as u128 + *run.iter().max()? as u128)
+}
+
+/// What to make up: `len` numbers checked against the `preamble` before them,
+/// with the ones at `invalid_at` made to fail
+#[derive(Debug, Clone)]
+struct StreamSpec {
+    len: usize,
+    preamble: usize,
+    invalid_at: Vec<usize>,
+    seed: u64,
+}
+
+/// A made up stream, and what the validator and weakness finder ought to say about it
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Stream {
+    numbers: Vec<u64>,
+    /// each invalid number's index, and a run before it that adds up to it if there is one
+    invalid: Vec<(usize, Option<Range<usize>>)>,
+}
+
+/// Every sum of two different numbers in `window`
+fn pair_sums(window: &[u64]) -> Vec<u64> {
+    let mut sums = Vec::new();
+    for (i, a) in window.iter().enumerate() {
+        sums.extend(window[i + 1..].iter().filter_map(|b| a.checked_add(*b)));
     }
-    None
+    sums
 }
 
-fn find_subsum(window: &[u32], needle: u32) -> Option<Vec<u32>> {
-    // if window is 10 things long, we wanna take 2, then 3, then 4, etc
-    // until we find a sum equalling the needle
-    for window_length in 2..window.len() {
-        for subwindow in window.windows(window_length) {
-            let subsum: u64 = subwindow.iter().map(|s| *s as u64).sum();
-            if subsum == needle as u64 {
-                return Some(subwindow.to_vec())
+/// Makes a stream to spec. Windows keep some zeros so valid numbers can stay
+/// small, which keeps the numbers bounded however long the stream is, and the
+/// invalid ones get a run before them that adds up to them where that works.
+fn generate(spec: &StreamSpec) -> Result<Stream, GenerateError> {
+    let p = spec.preamble;
+    if p < 2 {
+        return Err(GenerateError::PreambleTooShort(p));
+    }
+    let mut invalid_at = spec.invalid_at.clone();
+    invalid_at.sort_unstable();
+    invalid_at.dedup();
+    if let Some(at) = invalid_at.iter().find(|at| **at < p) {
+        return Err(GenerateError::InvalidTooEarly {
+            at: *at,
+            preamble: p,
+        });
+    }
+    if let Some(at) = invalid_at.iter().find(|at| **at >= spec.len) {
+        return Err(GenerateError::InvalidTooLate {
+            at: *at,
+            len: spec.len,
+        });
+    }
+
+    // enough zeros that an invalid number on its own can't leave us short
+    let zeros_wanted = p.min(3.max(p / 2));
+    let cap = 4 * p as u64;
+    let mut rng = XorShift::new(spec.seed);
+    let mut numbers: Vec<u64> = (0..p).map(|_| rng.below(2 * p) as u64 + 1).collect();
+    let mut zeros = 0;
+    while zeros < zeros_wanted {
+        let i = rng.below(p);
+        if numbers[i] != 0 {
+            numbers[i] = 0;
+            zeros += 1;
+        }
+    }
+    numbers.truncate(spec.len);
+
+    let mut invalid = Vec::new();
+    for at in p..spec.len {
+        let window = &numbers[at - p..at];
+        let sums = pair_sums(window);
+        let zeros = window.iter().filter(|n| **n == 0).count();
+        let n = if invalid_at.binary_search(&at).is_ok() {
+            let start = rng.below(at - 1);
+            let end = (start + 2 + rng.below(3)).min(at);
+            let run_sum = numbers[start..end]
+                .iter()
+                .try_fold(0u64, |sum, x| sum.checked_add(*x));
+            let (n, run) = match run_sum {
+                // a zero is the one thing that gets the zeros back
+                _ if zeros < 2 => (0, None),
+                Some(sum) if sum <= 4 * cap && !sums.contains(&sum) => (sum, Some(start..end)),
+                // there are fewer pair sums than that, so this always finds one
+                _ => ((0..).find(|n| !sums.contains(n)).unwrap(), None),
+            };
+            invalid.push((at, run));
+            n
+        } else if zeros >= 2 && zeros - usize::from(window[0] == 0) < zeros_wanted {
+            0
+        } else if window[0] != 0 && zeros > 0 && rng.below(2) == 0 {
+            // putting back the one that's leaving, plus a zero, is the only way
+            // to keep the smaller numbers around
+            window[0]
+        } else {
+            // no more zeros than we need, or they'd take over
+            let small: Vec<u64> = sums
+                .iter()
+                .copied()
+                .filter(|sum| *sum != 0 && *sum <= cap)
+                .collect();
+            match small.len() {
+                // out of zeros, so take the smallest and hope for an invalid zero,
+                // or everything is zeros, which is only the smallest preambles
+                0 => *sums.iter().min().ok_or(GenerateError::Overflow { at })?,
+                len => small[rng.below(len)],
             }
+        };
+        numbers.push(n);
+    }
+    Ok(Stream { numbers, invalid })
+}
+
+/// The value after `--name`, if it's there
+fn flag<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>, String> {
+    match args.iter().position(|a| a == name) {
+        Some(i) => args
+            .get(i + 1)
+            .and_then(|v| v.parse().ok())
+            .map(Some)
+            .ok_or_else(|| format!("{} needs a value", name)),
+        None => Ok(None),
+    }
+}
+
+/// `day9 generate <len> [--preamble N] [--seed S] [--invalid i,j,...]` writes a
+/// stream to stdout and what's wrong with it to stderr
+fn generate_command(args: &[String]) -> Result<(), String> {
+    let len = args
+        .get(2)
+        .and_then(|n| n.parse().ok())
+        .ok_or("usage: day9 generate <len> [--preamble N] [--seed S] [--invalid i,j,...]")?;
+    let invalid_at = match flag::<String>(args, "--invalid")? {
+        Some(list) => list
+            .split(',')
+            .map(|i| {
+                i.trim()
+                    .parse()
+                    .map_err(|_| format!("`{}` isn't an index", i))
+            })
+            .collect::<Result<Vec<usize>, String>>()?,
+        None => Vec::new(),
+    };
+    let spec = StreamSpec {
+        len,
+        preamble: flag(args, "--preamble")?.unwrap_or(25),
+        invalid_at,
+        seed: flag(args, "--seed")?.unwrap_or(2020),
+    };
+    let stream = generate(&spec).map_err(|e| e.to_string())?;
+    for n in &stream.numbers {
+        println!("{}", n);
+    }
+    for (at, run) in &stream.invalid {
+        match run {
+            Some(run) => eprintln!(
+                "{} is invalid, {}..{} adds up to it",
+                at, run.start, run.end
+            ),
+            None => eprintln!("{} is invalid", at),
         }
     }
-    None
+    Ok(())
 }
 
 fn main() {
-    /*
-    let mut r = BufReader::new(stdin());
-    let mut data = String::new();
-    r.read_to_string(&mut data).unwrap();
-    */
-    let data = include_str!("../inputs/day9.txt");
-
-    let lines = data
-        .lines()
-        .filter_map(|s| s.trim().parse().ok())
-        .collect::<Vec<u32>>();
-    let preamble_len = 25;
-
-    let out = lines.windows(preamble_len + 1)
-        .filter_map(|s| {
-            if valid_xmas(s).is_none() {
-                s.last().cloned()
-            } else {
-                None
-            }
-        });
+    // day9 [--preamble N] [--all] < numbers
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("generate") {
+        if let Err(e) = generate_command(&args) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let all = args.iter().any(|a| a == "--all");
+    let preamble_len = match flag(&args, "--preamble") {
+        Ok(None) => 25,
+        Ok(Some(n)) if n > 0 => n,
+        _ => {
+            eprintln!("--preamble needs a number bigger than 0");
+            std::process::exit(1);
+        }
+    };
 
-    for i in out {
+    // keep hold of everything for the weakness, but say what's invalid as it comes in
+    let mut lines = Vec::new();
+    let numbers = read_numbers(BufReader::new(stdin()))
+        .map(|n| {
+            n.unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })
+        })
+        .inspect(|n| lines.push(*n));
+    let bad: Vec<(usize, u64)> = invalid_numbers(numbers, preamble_len)
+        .inspect(|(index, n)| println!("{} at {} isn't valid", n, index))
+        .collect();
+    for (_, i) in bad {
         println!("first bad one is {}", i);
-        if let Some(x) = find_subsum(&lines, i) {
-            println!("{:?}", x);
-            let min = x.iter().min().unwrap();
-            let max = x.iter().max().unwrap();
-            println!("... key is {}", min + max);
+        let runs = if all {
+            subsum_ranges(&lines, i)
+        } else {
+            find_subsum(&lines, i).into_iter().collect()
+        };
+        for run in runs {
+            println!("{}..{}: {:?}", run.start, run.end, &lines[run.clone()]);
+            if let Some(key) = weakness(&lines[run]) {
+                println!("... key is {}", key);
+            }
         }
     }
 }
@@ -107,22 +406,240 @@ mod tests {
     fn subsum_thing_works() {
         let data = vec![2,3,4,5,6,8,9,10,11];
         let result = find_subsum(&data, 7);
-        assert_eq!(result, Some(vec![3,4]));
+        assert_eq!(result, Some(1..3));
+        assert_eq!(data[1..3], [3, 4]);
     }
 
     #[test]
     fn test_valid_number() {
-        let preamble_len = 5;
-        let mut windows = DATA.windows(preamble_len + 1);
-        let known = (15, 25);
-        assert_eq!(valid_xmas(windows.next().unwrap()), Some(known));
-
-        /*
-        for segment_data in DATA.windows(preamble_len + 1) {
-            let len = segment_data.len() - 1;
-            let preamble = &segment_data[0..len];
-            let r = valid_number(*segment_data.last().unwrap(), preamble);
-            dbg!(r);
-        }*/
+        let mut validator = Validator::new(5);
+        for n in DATA[..5].iter() {
+            assert_eq!(validator.push(*n as u64), Check::Preamble);
+        }
+        // 15 + 25
+        assert_eq!(validator.push(DATA[5] as u64), Check::Valid);
+    }
+
+    #[test]
+    fn validator_finds_the_example() {
+        let data: Vec<u64> = DATA.iter().map(|n| *n as u64).collect();
+        assert_eq!(
+            invalid_numbers(data.iter().copied(), 5).collect::<Vec<_>>(),
+            vec![(14, 127)]
+        );
+        let mut validator = Validator::new(5);
+        let checks: Vec<Check> = data[..7].iter().map(|n| validator.push(*n)).collect();
+        assert_eq!(checks[..5], [Check::Preamble; 5]);
+        assert_eq!(checks[5..], [Check::Valid, Check::Valid]);
+    }
+
+    #[test]
+    fn validator_matches_rescanning() {
+        let data = include_str!("../../inputs/day9.txt");
+        let numbers: Vec<u64> = read_numbers(data.as_bytes()).map(Result::unwrap).collect();
+        for preamble in [2, 5, 25].iter() {
+            let rescanned: Vec<(usize, u64)> = numbers
+                .windows(preamble + 1)
+                .enumerate()
+                .filter(|(_, w)| {
+                    let (n, before) = w.split_last().unwrap();
+                    !before.iter().enumerate().any(|(i, a)| {
+                        before
+                            .iter()
+                            .enumerate()
+                            .any(|(j, b)| i != j && a + b == *n)
+                    })
+                })
+                .map(|(i, w)| (i + preamble, w[*preamble]))
+                .collect();
+            let found: Vec<(usize, u64)> =
+                invalid_numbers(numbers.iter().copied(), *preamble).collect();
+            assert_eq!(found, rescanned);
+        }
+    }
+
+    #[test]
+    fn validator_keeps_duplicates_apart() {
+        // 3 + 3 only counts while both threes are in the window
+        let found: Vec<_> = invalid_numbers(vec![3, 3, 1, 6, 6], 2).collect();
+        assert_eq!(found, vec![(2, 1), (3, 6), (4, 6)]);
+        let found: Vec<_> = invalid_numbers(vec![3, 3, 6, 9], 2).collect();
+        assert_eq!(found, vec![]);
+    }
+
+    #[test]
+    fn reading_numbers() {
+        let read: Vec<_> = read_numbers("1\n\n 20 \n18446744073709551615\nx".as_bytes()).collect();
+        assert_eq!(
+            read,
+            vec![
+                Ok(1),
+                Ok(20),
+                Ok(u64::MAX),
+                Err(XmasError::BadNumber {
+                    line: 5,
+                    text: "x".to_string()
+                })
+            ]
+        );
+    }
+
+    #[test]
+    fn subsum_finds_the_example() {
+        let data: Vec<u64> = DATA.iter().map(|n| *n as u64).collect();
+        let run = find_subsum(&data, 127).unwrap();
+        assert_eq!(data[run.clone()], [15, 25, 47, 40]);
+        assert_eq!(weakness(&data[run]), Some(62));
+        // a number on its own doesn't count
+        assert_eq!(subsum_ranges(&data, 127), vec![2..6]);
+    }
+
+    #[test]
+    fn subsum_finds_them_all() {
+        let data = [1, 2, 3, 0, 3, 1, 5];
+        assert_eq!(subsum_ranges(&data, 6), vec![0..3, 0..4, 2..5, 5..7]);
+        assert_eq!(subsum_ranges(&[0, 0, 0], 0), vec![0..2, 0..3, 1..3]);
+        assert_eq!(subsum_ranges(&[5], 5), vec![]);
+        assert_eq!(subsum_ranges(&[], 0), vec![]);
+    }
+
+    #[test]
+    fn subsum_handles_big_numbers() {
+        let big = u64::MAX - 1;
+        let data = [big, 1, big, u64::MAX];
+        assert_eq!(subsum_ranges(&data, u64::MAX), vec![0..2, 1..3]);
+        assert_eq!(weakness(&[u64::MAX, u64::MAX]), Some(2 * u64::MAX as u128));
+    }
+
+    #[test]
+    fn subsum_matches_trying_everything() {
+        let data = include_str!("../../inputs/day9.txt");
+        let numbers: Vec<u64> = read_numbers(data.as_bytes()).map(Result::unwrap).collect();
+        let numbers = &numbers[..300];
+        for needle in [numbers[250], numbers[10] + numbers[11], 1].iter() {
+            let mut everything = Vec::new();
+            for start in 0..numbers.len() {
+                for end in start + 2..=numbers.len() {
+                    if numbers[start..end].iter().sum::<u64>() == *needle {
+                        everything.push(start..end);
+                    }
+                }
+            }
+            assert_eq!(subsum_ranges(numbers, *needle), everything);
+        }
+    }
+
+    #[test]
+    fn generated_streams_are_what_they_say() {
+        let mut rng = XorShift::new(49);
+        for preamble in [2, 3, 5, 25].iter() {
+            for seed in 0..20 {
+                let len = preamble + 1 + rng.below(300);
+                // a preamble apart gives the zeros time to come back, and with
+                // a preamble of 2 any invalid number uses them up for good
+                let mut invalid_at = Vec::new();
+                let mut at = preamble + rng.below(*preamble);
+                while *preamble > 2 && at < len && invalid_at.len() < 5 {
+                    invalid_at.push(at);
+                    at += preamble + rng.below(40);
+                }
+                let spec = StreamSpec {
+                    len,
+                    preamble: *preamble,
+                    invalid_at: invalid_at.clone(),
+                    seed,
+                };
+                let stream = generate(&spec).unwrap();
+                assert_eq!(stream.numbers.len(), len);
+                assert!(stream.numbers.iter().all(|n| *n <= 16 * *preamble as u64));
+                let found: Vec<usize> = invalid_numbers(stream.numbers.iter().copied(), *preamble)
+                    .map(|(at, _)| at)
+                    .collect();
+                let injected: Vec<usize> = stream.invalid.iter().map(|(at, _)| *at).collect();
+                assert_eq!(found, injected, "{:?}", spec);
+                for (at, run) in stream.invalid {
+                    if let Some(run) = run {
+                        let needle = stream.numbers[at];
+                        assert!(subsum_ranges(&stream.numbers, needle).contains(&run));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn generated_streams_can_go_on_forever() {
+        let spec = StreamSpec {
+            len: 100_000,
+            preamble: 5,
+            invalid_at: (1..100).map(|i| i * 1000).collect(),
+            seed: 3,
+        };
+        let stream = generate(&spec).unwrap();
+        assert!(stream.numbers.iter().all(|n| *n <= 80));
+        let found: Vec<usize> = invalid_numbers(stream.numbers.iter().copied(), 5)
+            .map(|(at, _)| at)
+            .collect();
+        assert_eq!(found, spec.invalid_at);
+    }
+
+    #[test]
+    fn generating_is_repeatable() {
+        let spec = StreamSpec {
+            len: 200,
+            preamble: 25,
+            invalid_at: vec![150, 30],
+            seed: 7,
+        };
+        let stream = generate(&spec).unwrap();
+        assert_eq!(generate(&spec).unwrap(), stream);
+        assert_eq!(
+            stream.invalid.iter().map(|(at, _)| *at).collect::<Vec<_>>(),
+            vec![30, 150]
+        );
+        let other = generate(&StreamSpec { seed: 8, ..spec }).unwrap();
+        assert_ne!(other.numbers, stream.numbers);
+    }
+
+    #[test]
+    fn generating_errors() {
+        let spec = StreamSpec {
+            len: 100,
+            preamble: 5,
+            invalid_at: vec![],
+            seed: 1,
+        };
+        let bad = |spec: StreamSpec| generate(&spec).unwrap_err();
+        assert_eq!(
+            bad(StreamSpec {
+                preamble: 1,
+                ..spec.clone()
+            }),
+            GenerateError::PreambleTooShort(1)
+        );
+        assert_eq!(
+            bad(StreamSpec {
+                invalid_at: vec![4],
+                ..spec.clone()
+            }),
+            GenerateError::InvalidTooEarly { at: 4, preamble: 5 }
+        );
+        assert_eq!(
+            bad(StreamSpec {
+                invalid_at: vec![100],
+                ..spec.clone()
+            }),
+            GenerateError::InvalidTooLate { at: 100, len: 100 }
+        );
+        // two invalid numbers in a row leave a preamble of 3 with one zero
+        assert!(matches!(
+            bad(StreamSpec {
+                len: 1000,
+                preamble: 3,
+                invalid_at: vec![3, 4],
+                ..spec
+            }),
+            GenerateError::Overflow { .. }
+        ));
     }
 }